@@ -0,0 +1,121 @@
+use reqwest::blocking::Client;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+	fs,
+	io::Read,
+	path::Path,
+	time::Duration,
+};
+
+// Patches are published next to the full binary as `<asset>.<from version>.bsdiff`,
+// or zstd-compressed as `<asset>.<from version>.bsdiff.zst`
+const MAX_PATCH_SIZE: u64 = 100_000_000;
+
+// A GitHub release asset, as the loader and the real integration both read it
+#[derive(Deserialize, Debug, Clone)]
+pub struct Asset {
+	pub name: String,
+	pub browser_download_url: String,
+	#[serde(default)]
+	pub digest: Option<String>,
+}
+
+// What differs between the loader and the real integration
+pub struct Options<'a> {
+	pub user_agent: &'a str,
+	pub log: fn(&str),
+	// Runs on a patched binary that matches its published hash, Err describes what is wrong with it
+	pub check: fn(&[u8]) -> Result<(), String>,
+}
+
+fn find_patch<'a>(assets: &'a [Asset], target: &str, from_version: &str) -> Option<&'a Asset> {
+	let plain = format!("{}.{}.bsdiff", target, from_version);
+	let compressed = format!("{}.zst", plain);
+	assets
+		.iter()
+		.find(|asset| asset.name == compressed)
+		.or_else(|| assets.iter().find(|asset| asset.name == plain))
+}
+
+// GitHub publishes asset hashes as `sha256:<hex>`
+pub fn expected_sha256(asset: &Asset) -> Option<&str> {
+	asset.digest.as_deref().and_then(|digest| digest.strip_prefix("sha256:"))
+}
+
+fn apply_patch(client: &Client, patch: &Asset, old: &[u8], user_agent: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+	let resp = client
+		.get(&patch.browser_download_url)
+		.header("User-Agent", user_agent)
+		.timeout(Duration::from_secs(30))
+		.send()?
+		.error_for_status()?;
+
+	let mut bytes = Vec::new();
+	resp.take(MAX_PATCH_SIZE).read_to_end(&mut bytes)?;
+
+	let mut new = Vec::new();
+	if patch.name.ends_with(".zst") {
+		let mut decoder = ruzstd::decoding::StreamingDecoder::new(bytes.as_slice())?;
+		bsdiff::patch(old, &mut decoder, &mut new)?;
+	} else {
+		bsdiff::patch(old, &mut bytes.as_slice(), &mut new)?;
+	}
+	Ok(new)
+}
+
+// Writes `out_path`, which may be `installed` itself, by applying a delta against `from_version`
+// to `installed` when the release has one. Returns false when the caller should fall back to a
+// full download
+pub fn try_update(
+	client: &Client,
+	assets: &[Asset],
+	target: &Asset,
+	installed: &Path,
+	from_version: &str,
+	out_path: &Path,
+	options: &Options,
+) -> bool {
+	let log = options.log;
+	let Some(patch) = find_patch(assets, &target.name, from_version) else {
+		return false;
+	};
+
+	let Some(expected) = expected_sha256(target) else {
+		log(&format!("No published hash for {}, ignoring delta", target.name));
+		return false;
+	};
+
+	let Ok(old) = fs::read(installed) else {
+		return false;
+	};
+
+	let new = match apply_patch(client, patch, &old, options.user_agent) {
+		Ok(new) => new,
+		Err(e) => {
+			log(&format!("Failed to apply {}: {}, downloading full binary", patch.name, e));
+			return false;
+		}
+	};
+
+	let actual = format!("{:x}", Sha256::digest(&new));
+	if !actual.eq_ignore_ascii_case(expected) {
+		log(&format!("Patched {} does not match its published hash, downloading full binary", target.name));
+		return false;
+	}
+
+	if let Err(e) = (options.check)(&new) {
+		log(&format!("Patched {} {}, downloading full binary", target.name, e));
+		return false;
+	}
+
+	let tmp_path = out_path.with_extension("tmp");
+	if let Err(e) = fs::write(&tmp_path, &new).and_then(|_| fs::rename(&tmp_path, out_path)) {
+		log(&format!("Failed to write patched {}: {}", target.name, e));
+		let _ = fs::remove_file(&tmp_path);
+		return false;
+	}
+
+	log(&format!("Updated {} from delta {}", target.name, patch.name));
+	true
+}
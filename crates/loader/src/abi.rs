@@ -0,0 +1,79 @@
+use std::ffi::{c_char, CStr, CString};
+
+use crate::{config, print_log, DATA_DIR, DEST_DIR, VERSION_FILE};
+
+// What this loader offers through LoaderContext. Bump it when fields are appended,
+// and raise MIN_COMPATIBLE_ABI only when a field changes meaning or goes away
+pub const ABI_VERSION: u32 = 1;
+pub const MIN_COMPATIBLE_ABI: u32 = 1;
+const CONTEXT_VERSION: u32 = 1;
+
+// Passed to `gmod_integration_init` of the real integration when it exports one. Strings are
+// NUL-terminated UTF-8 and only valid during the call, so the module has to copy what it keeps.
+// Fields are only ever appended, `struct_size` tells how many a module can read
+#[repr(C)]
+pub struct LoaderContext {
+	pub struct_version: u32,
+	pub struct_size: u32,
+	pub abi_version: u32,
+	pub min_compatible_abi: u32,
+	pub loader_version: *const c_char,
+	// updater.json with the loader's effective settings filled in, as JSON
+	pub config_json: *const c_char,
+	// Logs a message with the loader's prefix
+	pub log: extern "C" fn(*const c_char),
+	pub data_dir: *const c_char,
+	pub bin_dir: *const c_char,
+	pub version_file: *const c_char,
+}
+
+// Returns the loader ABI the module needs
+type InitFn = unsafe extern "C" fn(*const LoaderContext) -> u32;
+
+extern "C" fn log(msg: *const c_char) {
+	if msg.is_null() {
+		return;
+	}
+	let msg = unsafe { CStr::from_ptr(msg) };
+	print_log(&msg.to_string_lossy());
+}
+
+fn c_string(value: &str) -> CString {
+	CString::new(value.replace('\0', "")).unwrap_or_default()
+}
+
+// Tells a real integration that exports `gmod_integration_init` about this loader, and refuses
+// one that needs a newer loader. Modules without the entry point load as before
+pub unsafe fn handshake(lib: &libloading::Library) -> Result<(), String> {
+	let init: libloading::Symbol<InitFn> = match lib.get(b"gmod_integration_init") {
+		Ok(init) => init,
+		Err(_) => return Ok(()),
+	};
+
+	let loader_version = c_string(env!("CARGO_PKG_VERSION"));
+	let config_json = c_string(&config::resolved_json());
+	let data_dir = c_string(DATA_DIR);
+	let bin_dir = c_string(DEST_DIR);
+	let version_file = c_string(VERSION_FILE);
+
+	let context = LoaderContext {
+		struct_version: CONTEXT_VERSION,
+		struct_size: std::mem::size_of::<LoaderContext>() as u32,
+		abi_version: ABI_VERSION,
+		min_compatible_abi: MIN_COMPATIBLE_ABI,
+		loader_version: loader_version.as_ptr(),
+		config_json: config_json.as_ptr(),
+		log,
+		data_dir: data_dir.as_ptr(),
+		bin_dir: bin_dir.as_ptr(),
+		version_file: version_file.as_ptr(),
+	};
+
+	let required = init(&context);
+	if required > ABI_VERSION {
+		return Err(format!("it needs loader ABI {}, this loader ({}) provides {}", required, env!("CARGO_PKG_VERSION"), ABI_VERSION));
+	}
+
+	print_log(&format!("Handshake with real integration done (loader ABI {}, module needs {})", ABI_VERSION, required));
+	Ok(())
+}
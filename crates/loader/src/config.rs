@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+use crate::print_log;
+
+// Shared with the real module, which ignores the `loader` section
+const CONFIG_FILE: &str = "garrysmod/data/gm_integration/updater.json";
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ConfigFile {
+	loader: LoaderConfig,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct LoaderConfig {
+	pub load: LoadStrategy,
+	pub dev: DevConfig,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct DevConfig {
+	// Load local builds instead of releases, without any network access
+	pub enabled: bool,
+	// Build to load instead of the newest gmsv_gmod_integration_dev_<N>_<suffix>.dll
+	pub path: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadStrategy {
+	// dlopen the file in garrysmod/lua/bin
+	#[default]
+	Path,
+	// Linux only: verify the file, copy it into an anonymous memfd and dlopen that, so the
+	// code that runs is exactly the checked bytes even if the file is replaced meanwhile
+	Memfd,
+}
+
+pub fn load_config() -> LoaderConfig {
+	let content = match fs::read_to_string(CONFIG_FILE) {
+		Ok(content) => content,
+		Err(_) => return LoaderConfig::default(),
+	};
+
+	match serde_json::from_str::<ConfigFile>(&content) {
+		Ok(config) => config.loader,
+		Err(e) => {
+			print_log(&format!("Invalid {}, using defaults: {}", CONFIG_FILE, e));
+			LoaderConfig::default()
+		}
+	}
+}
+
+// The whole config file with the loader section as the loader sees it, for the real integration
+pub fn resolved_json() -> String {
+	let mut config = fs::read_to_string(CONFIG_FILE)
+		.ok()
+		.and_then(|content| serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&content).ok())
+		.unwrap_or_default();
+	if let Ok(loader) = serde_json::to_value(load_config()) {
+		config.insert("loader".to_string(), loader);
+	}
+	serde_json::Value::Object(config).to_string()
+}
@@ -0,0 +1,81 @@
+use gmod::lua::State;
+use std::{
+	cmp::Reverse,
+	fs,
+	path::{Path, PathBuf},
+};
+
+use crate::{config::DevConfig, delegate, get_platform_suffix, print_log, validate, DEST_DIR};
+
+// deploy.sh uploads gmsv_gmod_integration_dev_<N>_<suffix>.dll and, for the loader,
+// gmsv_gmod_integration_dev_loader_<N>_<suffix>.dll
+const REAL_PREFIX: &str = "gmsv_gmod_integration_dev_";
+const LOADER_PREFIX: &str = "gmsv_gmod_integration_dev_loader_";
+
+// Numbered builds in DEST_DIR with `prefix`, newest first
+fn numbered_builds(prefix: &str) -> Vec<(u64, PathBuf)> {
+	let suffix = format!("_{}.dll", get_platform_suffix());
+	let Ok(entries) = fs::read_dir(DEST_DIR) else {
+		return Vec::new();
+	};
+
+	let mut builds: Vec<(u64, PathBuf)> = entries
+		.flatten()
+		.filter_map(|entry| {
+			let name = entry.file_name().to_string_lossy().into_owned();
+			let number = name.strip_prefix(prefix)?.strip_suffix(&suffix)?.parse().ok()?;
+			Some((number, entry.path()))
+		})
+		.collect();
+	builds.sort_by_key(|(number, _)| Reverse(*number));
+	builds
+}
+
+// Removes every build but the newest, and never the one being loaded
+fn clean_up(builds: &[(u64, PathBuf)], loading: &Path) {
+	for (number, path) in builds.iter().skip(1) {
+		if path == loading {
+			continue;
+		}
+		match fs::remove_file(path) {
+			Ok(()) => print_log(&format!("Removed old dev build {} ({})", number, path.display())),
+			Err(e) => print_log(&format!("Failed to remove old dev build {}: {}", path.display(), e)),
+		}
+	}
+}
+
+// Loads a local build instead of a release. Nothing is downloaded and versions.json is not touched
+pub fn run(lua: State, config: &DevConfig) -> i32 {
+	print_log("Dev mode, skipping update checks");
+
+	let builds = numbered_builds(REAL_PREFIX);
+	let lib_name = match &config.path {
+		Some(path) => {
+			print_log(&format!("Using configured dev build {}", path));
+			PathBuf::from(path)
+		}
+		None => match builds.first() {
+			Some((number, path)) => {
+				print_log(&format!("Using dev build {} ({})", number, path.display()));
+				path.clone()
+			}
+			None => {
+				print_log(&format!("No dev build found in {}", DEST_DIR));
+				return 1;
+			}
+		},
+	};
+
+	clean_up(&builds, &lib_name);
+	clean_up(&numbered_builds(LOADER_PREFIX), &lib_name);
+
+	// A build for the wrong target would otherwise only fail inside dlopen
+	if let Ok(bytes) = fs::read(&lib_name) {
+		if let Err(e) = validate::check(&bytes) {
+			print_log(&format!("Dev build {} {}", lib_name.display(), e));
+			return 1;
+		}
+	}
+
+	delegate(lua, &lib_name, None, None)
+}
@@ -1,12 +1,20 @@
+mod abi;
+mod config;
+mod dev;
+mod load;
+mod self_update;
+mod validate;
+
 use chrono::Local;
 use gmod::{lua::State, gmod13_close, gmod13_open};
-use libloading;
+use gmod_integration_delta::{self as delta, Asset};
 use reqwest::blocking::Client;
 use serde::{Deserialize, Serialize};
 use std::{
 	fs,
 	io::copy,
-	path::PathBuf,
+	path::{Path, PathBuf},
+	sync::Mutex,
 	time::Duration,
 };
 
@@ -16,25 +24,36 @@ struct Release {
 	tag_name: String,
 }
 
-#[derive(Deserialize)]
-struct Asset {
-	name: String,
-	browser_download_url: String,
-}
-
 #[derive(Deserialize, Serialize, Default)]
 struct LoaderVersionCache {
 	gmod_integration_loader: Option<String>,
-	gmod_integration: Option<String>,
-	gwsockets: Option<String>,
-	reqwest: Option<String>,
+	// File in DEST_DIR holding the real integration that is loaded
+	#[serde(default)]
+	gmod_integration_active: Option<String>,
+	// SHA-256 of the active file when it was downloaded, checked before loading it from memory
+	#[serde(default)]
+	gmod_integration_sha256: Option<String>,
+	// Updates of the loader binary itself
+	#[serde(default)]
+	gmod_integration_loader_self: self_update::SelfUpdateState,
+	// Everything the real module tracks, kept as-is when the loader rewrites the file
+	#[serde(flatten)]
+	extra: serde_json::Map<String, serde_json::Value>,
 }
 
 const API_LATEST: &str =
 	"https://api.github.com/repos/gmod-integration/auto-loader/releases/latest";
 const DEST_DIR: &str = "garrysmod/lua/bin";
+const DATA_DIR: &str = "garrysmod/data/gm_integration";
 const VERSION_FILE: &str = "garrysmod/lua/bin/versions.json";
 
+// A patched binary has to pass the same checks as a download
+const DELTA: delta::Options = delta::Options { user_agent: "Gmod-Auto-Loader", log: print_log, check: validate::check };
+
+// Library loaded by gmod13_open, kept loaded so gmod13_close reaches the same copy even if a newer
+// one was recorded since, or it was loaded from memory
+static LOADED_LIBRARY: Mutex<Option<libloading::Library>> = Mutex::new(None);
+
 fn print_log(msg: &str) {
 	let time = Local::now().format("%Y-%m-%d %H:%M:%S");
 	println!(" | {} | Gmod Integration | Auto Loader: {}", time, msg);
@@ -47,6 +66,35 @@ fn load_loader_version_cache() -> LoaderVersionCache {
 		.unwrap_or_default()
 }
 
+fn save_loader_version_cache(version_cache: &LoaderVersionCache) -> bool {
+	if let Err(e) = (|| {
+		let content = serde_json::to_string_pretty(version_cache)?;
+		fs::write(VERSION_FILE, content)?;
+		Ok::<(), Box<dyn std::error::Error>>(())
+	})() {
+		print_log(&format!("Failed to save version cache: {}", e));
+		return false;
+	}
+	true
+}
+
+// Downloads get their own file so a copy that is loaded, by this or another server, is never overwritten
+fn versioned_file_name(tag: &str, suffix: &str) -> String {
+	let tag: String = tag
+		.chars()
+		.map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+		.collect();
+	format!("gmod_integration_{}_{}.dll", tag, suffix)
+}
+
+// The recorded real integration, or the fixed name older loaders wrote to
+fn active_library_path(version_cache: &LoaderVersionCache) -> PathBuf {
+	match &version_cache.gmod_integration_active {
+		Some(file) => Path::new(DEST_DIR).join(file),
+		None => Path::new(DEST_DIR).join(format!("gmsv_gmod_integration_{}.dll", get_platform_suffix())),
+	}
+}
+
 fn get_platform_suffix() -> &'static str {
 	if cfg!(target_os = "windows") {
 		if cfg!(target_arch = "x86_64") { "win64" } else { "win32" }
@@ -55,7 +103,7 @@ fn get_platform_suffix() -> &'static str {
 	}
 }
 
-fn download_asset(client: &Client, asset: &Asset) -> Result<(), Box<dyn std::error::Error>> {
+fn download_asset(client: &Client, asset: &Asset, out_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
 	// Download the asset from GitHub releases
 	let mut resp = client
 		.get(&asset.browser_download_url)
@@ -68,10 +116,6 @@ fn download_asset(client: &Client, asset: &Asset) -> Result<(), Box<dyn std::err
 		return Err(format!("HTTP error: {}", resp.status()).into());
 	}
 
-	// Create output path and temporary file for safe downloading
-	let mut out_path = PathBuf::from(DEST_DIR);
-	out_path.push(&asset.name);
-
 	// Ensure parent directory exists
 	if let Some(parent) = out_path.parent() {
 		fs::create_dir_all(parent)?;
@@ -88,64 +132,115 @@ fn download_asset(client: &Client, asset: &Asset) -> Result<(), Box<dyn std::err
 		return Err("Downloaded file is empty".into());
 	}
 
+	// An error page or a build for another platform must never become the active binary
+	if let Err(e) = validate::check(&fs::read(&tmp_path)?) {
+		fs::remove_file(&tmp_path)?;
+		return Err(format!("{} {}", asset.name, e).into());
+	}
+
 	// Atomically rename temporary file to final destination
-	fs::rename(tmp_path, &out_path)?;
+	fs::rename(tmp_path, out_path)?;
 	
 	print_log(&format!("Downloaded {}", asset.name));
 	Ok(())
 }
 
 fn delegate_to_real_loader(lua: State) -> i32 {
-	unsafe {
-		// Load the real integration library dynamically
-		let suffix = get_platform_suffix();
-		let lib_name = format!("{}/gmsv_gmod_integration_{}.dll", DEST_DIR, suffix);
+	// Load the real integration library recorded as active
+	let mut version_cache = load_loader_version_cache();
+	let lib_name = active_library_path(&version_cache);
+	let expected_sha256 = version_cache.gmod_integration_sha256.clone();
+	delegate(lua, &lib_name, expected_sha256.as_deref(), Some(&mut version_cache))
+}
 
+// Loads `lib_name` and calls its gmod13_open. With a version cache, the outcome also decides
+// the trial of a freshly swapped-in loader
+fn delegate(lua: State, lib_name: &Path, expected_sha256: Option<&str>, mut version_cache: Option<&mut LoaderVersionCache>) -> i32 {
+	unsafe {
 		// Check if file exists before trying to load
-		if !std::path::Path::new(&lib_name).exists() {
-			print_log(&format!("Real integration file not found: {}", lib_name));
+		if !lib_name.exists() {
+			print_log(&format!("Real integration file not found: {}", lib_name.display()));
+			// Nothing to load is not the loader's fault
+			if let Some(version_cache) = version_cache.as_deref_mut() {
+				self_update::confirm(version_cache);
+			}
 			return 1;
 		}
 
-		let lib = match libloading::Library::new(&lib_name) {
+		let strategy = config::load_config().load;
+		let lib = match load::open(lib_name, expected_sha256, strategy) {
 			Ok(lib) => lib,
 			Err(e) => {
 				print_log(&format!("Failed to load real integration: {}", e));
+				if let Some(version_cache) = version_cache.as_deref_mut() {
+					match e {
+						// A damaged or wrong download is not the loader's fault
+						load::Error::Integrity(_) => self_update::confirm(version_cache),
+						load::Error::Load(_) => self_update::fail(version_cache, "it could not load the real integration"),
+					}
+				}
 				return 1;
 			}
 		};
 
+		if let Err(e) = abi::handshake(&lib) {
+			print_log(&format!("Refusing to load real integration: {}", e));
+			// The module wants a newer loader, an older one would be refused as well
+			if let Some(version_cache) = version_cache.as_deref_mut() {
+				self_update::confirm(version_cache);
+			}
+			return 1;
+		}
+
 		// Get the gmod13_open function from the real integration
-		let func: libloading::Symbol<unsafe extern "C" fn(State) -> i32> = match lib.get(b"gmod13_open") {
-			Ok(func) => func,
+		let func: unsafe extern "C" fn(State) -> i32 = match lib.get(b"gmod13_open") {
+			Ok(func) => *func,
 			Err(e) => {
 				print_log(&format!("Failed to find gmod13_open symbol: {}", e));
+				// A module without the entry point is broken whichever loader opens it
+				if let Some(version_cache) = version_cache.as_deref_mut() {
+					self_update::confirm(version_cache);
+				}
 				return 1;
 			}
 		};
-		
-		print_log("Delegated to real integration");
+		if let Some(version_cache) = version_cache {
+			self_update::confirm(version_cache);
+		}
+
+		if let Ok(mut loaded) = LOADED_LIBRARY.lock() {
+			*loaded = Some(lib);
+		}
+		print_log(&format!("Delegated to real integration ({})", lib_name.display()));
 		func(lua)
 	}
 }
 
 #[gmod13_open]
 fn gmod13_open(lua: State) -> i32 {
+	let dev = config::load_config().dev;
+	if dev.enabled {
+		return dev::run(lua, &dev);
+	}
+
 	print_log("Checking for updates...");
 	
+	let mut version_cache = load_loader_version_cache();
+	self_update::startup(&mut version_cache);
+
 	// Ensure destination directory exists
 	if let Err(e) = fs::create_dir_all(DEST_DIR) {
 		print_log(&format!("Failed to create directory: {}", e));
+		// Not the loader's fault, so it doesn't count against a loader on trial
+		self_update::confirm(&mut version_cache);
 		return 1; // Don't delegate if we can't even create directories
 	}
-
-	let mut version_cache = load_loader_version_cache();
 	let client = Client::new();
 
 	// Check if the real integration file exists on disk
 	let suffix = get_platform_suffix();
-	let lib_path = format!("{}/gmsv_gmod_integration_{}.dll", DEST_DIR, suffix);
-	let file_exists = std::path::Path::new(&lib_path).exists();
+	let lib_path = active_library_path(&version_cache);
+	let file_exists = lib_path.exists();
 
 	// Fetch latest release information from GitHub API
 	let release: Release = match client
@@ -159,11 +254,8 @@ fn gmod13_open(lua: State) -> i32 {
 		Ok(r) => r,
 		Err(e) => {
 			print_log(&format!("Error fetching release: {}", e));
-			// If file exists, still try to delegate
-			if file_exists {
-				return delegate_to_real_loader(lua);
-			}
-			return 1;
+			// Still try to delegate, which also ends a loader trial when there is nothing to load
+			return delegate_to_real_loader(lua);
 		}
 	};
 
@@ -178,6 +270,8 @@ fn gmod13_open(lua: State) -> i32 {
 		return delegate_to_real_loader(lua);
 	}
 
+	self_update::stage(&client, &release, &mut version_cache);
+
 	// Skip update if version matches and file exists
 	if let Some(current_version) = &version_cache.gmod_integration_loader {
 		if current_version == &release.tag_name && file_exists {
@@ -196,16 +290,25 @@ fn gmod13_open(lua: State) -> i32 {
 
 	// Download the appropriate binary for current platform
 	let target_asset = format!("gmsv_gmod_integration_{}.dll", suffix);
+	let new_file = versioned_file_name(&release.tag_name, suffix);
+	let new_path = Path::new(DEST_DIR).join(&new_file);
 	let mut found_asset = false;
-	
+
 	for asset in &release.assets {
 		if asset.name == target_asset {
 			found_asset = true;
-			if let Err(e) = download_asset(&client, asset) {
+
+			// Prefer a small delta against the installed binary when the release ships one
+			if let Some(current_version) = &version_cache.gmod_integration_loader {
+				if file_exists && delta::try_update(&client, &release.assets, asset, &lib_path, current_version, &new_path, &DELTA) {
+					break;
+				}
+			}
+
+			if let Err(e) = download_asset(&client, asset, &new_path) {
 				print_log(&format!("Failed to download {}: {}", asset.name, e));
 				// Clean up any partial download
-				let partial_path = PathBuf::from(DEST_DIR).join(&asset.name);
-				let _ = fs::remove_file(partial_path);
+				let _ = fs::remove_file(new_path.with_extension("tmp"));
 				return delegate_to_real_loader(lua);
 			}
 			break;
@@ -219,13 +322,9 @@ fn gmod13_open(lua: State) -> i32 {
 
 	// Update version cache with new version
 	version_cache.gmod_integration_loader = Some(release.tag_name);
-	if let Err(e) = (|| {
-		let content = serde_json::to_string_pretty(&version_cache)?;
-		fs::write(VERSION_FILE, content)?;
-		Ok::<(), Box<dyn std::error::Error>>(())
-	})() {
-		print_log(&format!("Failed to save version cache: {}", e));
-	}
+	version_cache.gmod_integration_active = Some(new_file);
+	version_cache.gmod_integration_sha256 = fs::read(&new_path).ok().map(|bytes| load::sha256_hex(&bytes));
+	save_loader_version_cache(&version_cache);
 
 	print_log("Update completed, delegating to real integration");
 	delegate_to_real_loader(lua)
@@ -233,22 +332,32 @@ fn gmod13_open(lua: State) -> i32 {
 
 #[gmod13_close]
 fn gmod13_close(lua: State) -> i32 {
-	unsafe {
-		// Load and call the real integration's close function
-		let suffix = get_platform_suffix();
-		let lib_name = format!("{}/gmsv_gmod_integration_{}.dll", DEST_DIR, suffix);
-
-		// Check if file exists before trying to load
-		if !std::path::Path::new(&lib_name).exists() {
-			print_log(&format!("Real integration file not found during close: {}", lib_name));
-			return 0; // Don't fail the close operation
-		}
+	// The process is done with the loader, a staged one can take its place
+	if !config::load_config().dev.enabled {
+		self_update::swap_staged(&mut load_loader_version_cache());
+	}
 
-		let lib = match libloading::Library::new(&lib_name) {
-			Ok(lib) => lib,
-			Err(e) => {
-				print_log(&format!("Failed to load real integration during close: {}", e));
-				return 0; // Don't fail the close operation
+	unsafe {
+		// Call the real integration's close function on the copy gmod13_open loaded
+		let loaded = LOADED_LIBRARY.lock().ok().and_then(|mut loaded| loaded.take());
+		let lib = match loaded {
+			Some(lib) => lib,
+			None => {
+				let lib_name = active_library_path(&load_loader_version_cache());
+
+				// Check if file exists before trying to load
+				if !lib_name.exists() {
+					print_log(&format!("Real integration file not found during close: {}", lib_name.display()));
+					return 0; // Don't fail the close operation
+				}
+
+				match libloading::Library::new(&lib_name) {
+					Ok(lib) => lib,
+					Err(e) => {
+						print_log(&format!("Failed to load real integration during close: {}", e));
+						return 0; // Don't fail the close operation
+					}
+				}
 			}
 		};
 
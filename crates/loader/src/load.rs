@@ -0,0 +1,95 @@
+use libloading::Library;
+use sha2::{Digest, Sha256};
+use std::{fmt, fs, path::Path};
+
+use crate::{config::LoadStrategy, print_log, validate};
+
+pub enum Error {
+	// The file is unreadable, not a module for this server or not the one that was downloaded
+	Integrity(String),
+	// A sound file could not be loaded
+	Load(String),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::Integrity(e) | Error::Load(e) => f.write_str(e),
+		}
+	}
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+	format!("{:x}", Sha256::digest(bytes))
+}
+
+unsafe fn open_path(path: &Path) -> Result<Library, String> {
+	Library::new(path).map_err(|e| e.to_string())
+}
+
+// The fd is closed again once dlopen has mapped the library
+#[cfg(target_os = "linux")]
+unsafe fn open_memfd(bytes: &[u8]) -> Result<Library, String> {
+	use std::{fs::File, io::Write, os::unix::io::FromRawFd};
+
+	let fd = libc::memfd_create(c"gmod_integration".as_ptr(), libc::MFD_CLOEXEC);
+	if fd < 0 {
+		return Err(format!("memfd_create failed: {}", std::io::Error::last_os_error()));
+	}
+
+	let mut file = File::from_raw_fd(fd);
+	file.write_all(bytes).map_err(|e| format!("Failed to write memfd: {}", e))?;
+	Library::new(format!("/proc/self/fd/{}", fd)).map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+unsafe fn open_memfd(_bytes: &[u8]) -> Result<Library, String> {
+	Err("memfd loading is only available on Linux".to_string())
+}
+
+fn verify(path: &Path, bytes: &[u8], expected_sha256: Option<&str>) -> Result<(), String> {
+	validate::check(bytes).map_err(|e| format!("{} {}", path.display(), e))?;
+	match expected_sha256 {
+		Some(expected) if !sha256_hex(bytes).eq_ignore_ascii_case(expected) => {
+			Err(format!("{} does not match its recorded hash, refusing to load it", path.display()))
+		}
+		_ => Ok(()),
+	}
+}
+
+// Blames a failed load on the file when it doesn't pass the checks, otherwise on loading itself
+fn classify(path: &Path, expected_sha256: Option<&str>, error: String) -> Error {
+	match fs::read(path) {
+		Ok(bytes) => match verify(path, &bytes, expected_sha256) {
+			Ok(()) => Error::Load(error),
+			Err(e) => Error::Integrity(e),
+		},
+		Err(e) => Error::Integrity(format!("Failed to read {}: {}", path.display(), e)),
+	}
+}
+
+// Loads the real integration. With the memfd strategy the file is read once, validated, checked
+// against the hash recorded when it was downloaded, and loaded from memory. A memfd that can't be
+// created or loaded falls back to loading the file by path, a hash mismatch does not
+pub unsafe fn open(path: &Path, expected_sha256: Option<&str>, strategy: LoadStrategy) -> Result<Library, Error> {
+	if strategy == LoadStrategy::Path {
+		return open_path(path).map_err(|e| classify(path, expected_sha256, e));
+	}
+
+	let bytes = fs::read(path).map_err(|e| Error::Integrity(format!("Failed to read {}: {}", path.display(), e)))?;
+	verify(path, &bytes, expected_sha256).map_err(Error::Integrity)?;
+	if expected_sha256.is_none() {
+		print_log(&format!("No recorded hash for {}, loading it unverified", path.display()));
+	}
+
+	match open_memfd(&bytes) {
+		Ok(lib) => {
+			print_log(&format!("Loaded {} from memory", path.display()));
+			Ok(lib)
+		}
+		Err(e) => {
+			print_log(&format!("Failed to load {} from memory: {}, loading it by path", path.display(), e));
+			open_path(path).map_err(Error::Load)
+		}
+	}
+}
@@ -0,0 +1,193 @@
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use std::{
+	fs,
+	path::PathBuf,
+	sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+	download_asset, get_platform_suffix, load, print_log, save_loader_version_cache, validate, LoaderVersionCache,
+	Release, DEST_DIR,
+};
+
+// The process keeps running the loader it started with, so a swap done now only counts from the next start
+static SWAPPED_THIS_RUN: AtomicBool = AtomicBool::new(false);
+
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct SelfUpdateState {
+	// Release the loader binary in DEST_DIR came from
+	installed: Option<String>,
+	// Release waiting next to the loader to be swapped in
+	staged: Option<String>,
+	// Set while a swapped-in loader has not yet passed its self-check
+	trial: Option<Trial>,
+	// Releases whose loader failed its self-check, never staged again
+	rejected: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Trial {
+	previous: Option<String>,
+	// Starts of the new loader so far. One that never confirms was a crash
+	attempts: u32,
+}
+
+fn asset_name() -> String {
+	format!("gmsv_gmod_integration_loader_{}.dll", get_platform_suffix())
+}
+
+fn current_path() -> PathBuf {
+	PathBuf::from(DEST_DIR).join(asset_name())
+}
+
+fn sibling(extension: &str) -> PathBuf {
+	PathBuf::from(DEST_DIR).join(format!("{}.{}", asset_name(), extension))
+}
+
+// Puts the previous loader back for the next start
+fn rollback(cache: &mut LoaderVersionCache, reason: &str) {
+	let state = &mut cache.gmod_integration_loader_self;
+	let Some(trial) = state.trial.take() else {
+		return;
+	};
+
+	let failed = state.installed.take().unwrap_or_else(|| "unknown".to_string());
+	print_log(&format!("Loader {} failed its self-check ({}), rolling back", failed, reason));
+
+	// A loaded DLL can be renamed on Windows but not replaced
+	let _ = fs::rename(current_path(), sibling("failed"));
+	match fs::rename(sibling("previous"), current_path()) {
+		Ok(()) => print_log(&format!("Restored loader {}, active from the next start", trial.previous.as_deref().unwrap_or("unknown"))),
+		Err(e) => {
+			print_log(&format!("Failed to restore the previous loader: {}", e));
+			let _ = fs::rename(sibling("failed"), current_path());
+		}
+	}
+
+	state.installed = trial.previous;
+	state.rejected.push(failed);
+	save_loader_version_cache(cache);
+}
+
+// Moves a staged loader into place. Loaders are only ever renamed, never written over
+pub fn swap_staged(cache: &mut LoaderVersionCache) {
+	let state = &mut cache.gmod_integration_loader_self;
+	let Some(tag) = state.staged.clone() else {
+		return;
+	};
+	let staged = sibling("staged");
+	if !staged.exists() {
+		state.staged = None;
+		save_loader_version_cache(cache);
+		return;
+	}
+
+	let current = current_path();
+	let _ = fs::remove_file(sibling("previous"));
+	if let Err(e) = fs::rename(&current, sibling("previous")) {
+		print_log(&format!("Failed to move the current loader aside: {}", e));
+		return;
+	}
+	if let Err(e) = fs::rename(&staged, &current) {
+		print_log(&format!("Failed to swap in loader {}: {}", tag, e));
+		let _ = fs::rename(sibling("previous"), &current);
+		return;
+	}
+
+	SWAPPED_THIS_RUN.store(true, Ordering::SeqCst);
+	state.trial = Some(Trial { previous: state.installed.take(), attempts: 0 });
+	state.installed = Some(tag.clone());
+	state.staged = None;
+	save_loader_version_cache(cache);
+	print_log(&format!("Swapped in loader {}, active from the next start", tag));
+}
+
+// Runs before anything else on startup: finishes a swap that was left pending, and counts
+// starts of a loader on trial, rolling it back when the last one never confirmed
+pub fn startup(cache: &mut LoaderVersionCache) {
+	// Lua can be reopened in the same process, which still runs the loader it started with
+	if SWAPPED_THIS_RUN.load(Ordering::SeqCst) {
+		return;
+	}
+
+	if let Some(trial) = cache.gmod_integration_loader_self.trial.as_mut() {
+		if trial.attempts > 0 {
+			rollback(cache, "the previous start did not complete");
+			return;
+		}
+		trial.attempts += 1;
+
+		// Self-check: the state file is writable and the binary on disk is a valid loader
+		if !save_loader_version_cache(cache) {
+			rollback(cache, "versions.json can't be written");
+			return;
+		}
+		let own = fs::read(current_path()).map_err(|e| e.to_string()).and_then(|bytes| validate::check(&bytes));
+		if let Err(e) = own {
+			rollback(cache, &format!("its binary {}", e));
+			return;
+		}
+	}
+
+	swap_staged(cache);
+}
+
+// Called once the real integration is loaded, which ends the trial of a new loader
+pub fn confirm(cache: &mut LoaderVersionCache) {
+	if SWAPPED_THIS_RUN.load(Ordering::SeqCst) || cache.gmod_integration_loader_self.trial.is_none() {
+		return;
+	}
+
+	let state = &mut cache.gmod_integration_loader_self;
+	state.trial = None;
+	let _ = fs::remove_file(sibling("failed"));
+	print_log(&format!("Loader {} passed its self-check", state.installed.as_deref().unwrap_or("unknown")));
+	save_loader_version_cache(cache);
+}
+
+// Called when the real integration could not be loaded
+pub fn fail(cache: &mut LoaderVersionCache, reason: &str) {
+	if !SWAPPED_THIS_RUN.load(Ordering::SeqCst) {
+		rollback(cache, reason);
+	}
+}
+
+// Downloads the loader of `release` next to the running one when it differs
+pub fn stage(client: &Client, release: &Release, cache: &mut LoaderVersionCache) {
+	let state = &cache.gmod_integration_loader_self;
+	let tag = &release.tag_name;
+	if state.installed.as_ref() == Some(tag) || state.staged.as_ref() == Some(tag) || state.trial.is_some() {
+		return;
+	}
+	if state.rejected.contains(tag) {
+		return;
+	}
+
+	let name = asset_name();
+	let Some(asset) = release.assets.iter().find(|asset| asset.name == name) else {
+		return;
+	};
+
+	// A loader installed by hand may already be this release
+	let expected = asset.digest.as_deref().and_then(|digest| digest.strip_prefix("sha256:"));
+	let current = fs::read(current_path()).ok().map(|bytes| load::sha256_hex(&bytes));
+	if let (Some(expected), Some(current)) = (expected, current) {
+		if expected.eq_ignore_ascii_case(&current) {
+			cache.gmod_integration_loader_self.installed = Some(tag.clone());
+			save_loader_version_cache(cache);
+			return;
+		}
+	}
+
+	print_log(&format!("Staging loader {}", tag));
+	if let Err(e) = download_asset(client, asset, &sibling("staged")) {
+		print_log(&format!("Failed to download {}: {}", name, e));
+		return;
+	}
+
+	cache.gmod_integration_loader_self.staged = Some(tag.clone());
+	save_loader_version_cache(cache);
+	print_log(&format!("Loader {} staged, it is swapped in when the server shuts down", tag));
+}
@@ -0,0 +1,347 @@
+// Checks a downloaded binary before it is recorded as the real integration: it has to be a
+// shared library for this platform and architecture that exports the GMod entry points
+const REQUIRED_EXPORTS: &[&str] = &["gmod13_open", "gmod13_close"];
+
+// ELF e_machine and PE Machine values of the running process
+struct Arch {
+	name: &'static str,
+	bits: u8,
+	elf_machine: u16,
+	pe_machine: u16,
+}
+
+fn current_arch() -> Option<Arch> {
+	if cfg!(target_arch = "x86_64") {
+		Some(Arch { name: "x86_64", bits: 64, elf_machine: 62, pe_machine: 0x8664 })
+	} else if cfg!(target_arch = "x86") {
+		Some(Arch { name: "x86", bits: 32, elf_machine: 3, pe_machine: 0x14c })
+	} else if cfg!(target_arch = "aarch64") {
+		Some(Arch { name: "aarch64", bits: 64, elf_machine: 183, pe_machine: 0xaa64 })
+	} else {
+		None
+	}
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+	Some(u16::from_le_bytes(bytes.get(offset..offset.checked_add(2)?)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+	Some(u32::from_le_bytes(bytes.get(offset..offset.checked_add(4)?)?.try_into().ok()?))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+	Some(u64::from_le_bytes(bytes.get(offset..offset.checked_add(8)?)?.try_into().ok()?))
+}
+
+// A word that is 4 bytes in 32-bit and 8 bytes in 64-bit files
+fn read_word(bytes: &[u8], offset: usize, wide: bool) -> Option<usize> {
+	if wide {
+		usize::try_from(read_u64(bytes, offset)?).ok()
+	} else {
+		usize::try_from(read_u32(bytes, offset)?).ok()
+	}
+}
+
+fn read_c_str(bytes: &[u8], offset: usize) -> Option<&str> {
+	let rest = bytes.get(offset..)?;
+	let end = rest.iter().position(|byte| *byte == 0)?;
+	std::str::from_utf8(&rest[..end]).ok()
+}
+
+// Defined global or weak symbols of the dynamic symbol table
+fn elf_exports(bytes: &[u8], wide: bool) -> Option<Vec<&str>> {
+	const SHT_DYNSYM: u32 = 11;
+
+	let (shoff, shentsize, shnum) = if wide {
+		(read_word(bytes, 0x28, true)?, read_u16(bytes, 0x3a)? as usize, read_u16(bytes, 0x3c)? as usize)
+	} else {
+		(read_word(bytes, 0x20, false)?, read_u16(bytes, 0x2e)? as usize, read_u16(bytes, 0x30)? as usize)
+	};
+	// sh_offset, sh_size, sh_link and sh_entsize
+	let fields: [usize; 4] = if wide { [0x18, 0x20, 0x28, 0x38] } else { [0x10, 0x14, 0x18, 0x24] };
+	let section = |index: usize| -> Option<(u32, usize, usize, usize, usize)> {
+		let header = shoff.checked_add(index.checked_mul(shentsize)?)?;
+		bytes.get(header..header.checked_add(shentsize)?)?;
+		Some((
+			read_u32(bytes, header + 4)?,
+			read_word(bytes, header + fields[0], wide)?,
+			read_word(bytes, header + fields[1], wide)?,
+			read_u32(bytes, header + fields[2])? as usize,
+			read_word(bytes, header + fields[3], wide)?,
+		))
+	};
+
+	let (_, symbols, size, link, entsize) = (0..shnum).filter_map(section).find(|(kind, ..)| *kind == SHT_DYNSYM)?;
+	let (_, strings, ..) = section(link)?;
+	if entsize == 0 {
+		return None;
+	}
+
+	let mut exports = Vec::new();
+	for index in 0..size / entsize {
+		let symbol = symbols.checked_add(index.checked_mul(entsize)?)?;
+		bytes.get(symbol..symbol.checked_add(entsize)?)?;
+		let name = read_u32(bytes, symbol)? as usize;
+		let (info, shndx) = if wide {
+			(*bytes.get(symbol + 4)?, read_u16(bytes, symbol + 6)?)
+		} else {
+			(*bytes.get(symbol + 12)?, read_u16(bytes, symbol + 14)?)
+		};
+		let binding = info >> 4;
+		if shndx != 0 && (binding == 1 || binding == 2) {
+			if let Some(name) = read_c_str(bytes, strings.checked_add(name)?) {
+				exports.push(name);
+			}
+		}
+	}
+	Some(exports)
+}
+
+fn check_elf<'a>(bytes: &'a [u8], arch: Option<&Arch>) -> Result<Vec<&'a str>, String> {
+	if cfg!(target_os = "windows") {
+		return Err("is an ELF binary, this server needs a Windows DLL".to_string());
+	}
+
+	let wide = match bytes.get(4) {
+		Some(1) => false,
+		Some(2) => true,
+		_ => return Err("has an unknown ELF class".to_string()),
+	};
+	if bytes.get(5) != Some(&1) {
+		return Err("is not a little-endian ELF binary".to_string());
+	}
+	// ET_DYN
+	if read_u16(bytes, 0x10) != Some(3) {
+		return Err("is not an ELF shared library".to_string());
+	}
+
+	if let Some(arch) = arch {
+		let machine = read_u16(bytes, 0x12).unwrap_or(0);
+		if machine != arch.elf_machine || wide != (arch.bits == 64) {
+			return Err(format!("is built for ELF machine {} ({}-bit), this server runs {}", machine, if wide { 64 } else { 32 }, arch.name));
+		}
+	}
+
+	elf_exports(bytes, wide).ok_or_else(|| "has no readable dynamic symbol table".to_string())
+}
+
+// Names in the export directory
+fn pe_exports(bytes: &[u8], optional: usize, wide: bool, sections: usize, section_count: usize) -> Option<Vec<&str>> {
+	let directories = optional + if wide { 112 } else { 96 };
+	let directory_count = read_u32(bytes, optional + if wide { 108 } else { 92 })?;
+	if directory_count == 0 {
+		return Some(Vec::new());
+	}
+	let export_rva = read_u32(bytes, directories)?;
+	if export_rva == 0 {
+		return Some(Vec::new());
+	}
+
+	let to_offset = |rva: u32| -> Option<usize> {
+		(0..section_count).find_map(|index| {
+			let header = sections + index * 40;
+			let virtual_size = read_u32(bytes, header + 8)?;
+			let virtual_address = read_u32(bytes, header + 12)?;
+			let raw_size = read_u32(bytes, header + 16)?;
+			let raw_offset = read_u32(bytes, header + 20)?;
+			let end = virtual_address.checked_add(virtual_size.max(raw_size))?;
+			if rva >= virtual_address && rva < end { (raw_offset as usize).checked_add((rva - virtual_address) as usize) } else { None }
+		})
+	};
+
+	let directory = to_offset(export_rva)?;
+	let name_count = read_u32(bytes, directory.checked_add(24)?)? as usize;
+	let names = to_offset(read_u32(bytes, directory.checked_add(32)?)?)?;
+
+	let mut exports = Vec::new();
+	for index in 0..name_count {
+		let name = to_offset(read_u32(bytes, names.checked_add(index.checked_mul(4)?)?)?)?;
+		if let Some(name) = read_c_str(bytes, name) {
+			exports.push(name);
+		}
+	}
+	Some(exports)
+}
+
+fn check_pe<'a>(bytes: &'a [u8], arch: Option<&Arch>) -> Result<Vec<&'a str>, String> {
+	if !cfg!(target_os = "windows") {
+		return Err("is a Windows DLL, this server needs an ELF shared library".to_string());
+	}
+
+	let pe = read_u32(bytes, 0x3c).map(|offset| offset as usize).ok_or("has a truncated DOS header")?;
+	if pe.checked_add(4).and_then(|end| bytes.get(pe..end)) != Some(b"PE\0\0") {
+		return Err("has no PE signature".to_string());
+	}
+
+	let coff = pe + 4;
+	let machine = read_u16(bytes, coff).ok_or("has a truncated COFF header")?;
+	let section_count = read_u16(bytes, coff + 2).ok_or("has a truncated COFF header")? as usize;
+	let optional_size = read_u16(bytes, coff + 16).ok_or("has a truncated COFF header")? as usize;
+	let characteristics = read_u16(bytes, coff + 18).ok_or("has a truncated COFF header")?;
+	// IMAGE_FILE_DLL
+	if characteristics & 0x2000 == 0 {
+		return Err("is not a DLL".to_string());
+	}
+
+	let optional = coff + 20;
+	let wide = match read_u16(bytes, optional) {
+		Some(0x10b) => false,
+		Some(0x20b) => true,
+		_ => return Err("has an unknown PE optional header".to_string()),
+	};
+
+	if let Some(arch) = arch {
+		if machine != arch.pe_machine || wide != (arch.bits == 64) {
+			return Err(format!("is built for PE machine {:#x} ({}-bit), this server runs {}", machine, if wide { 64 } else { 32 }, arch.name));
+		}
+	}
+
+	pe_exports(bytes, optional, wide, optional + optional_size, section_count).ok_or_else(|| "has an unreadable export table".to_string())
+}
+
+// Err describes what is wrong with the binary, to follow its name in a log message
+pub fn check(bytes: &[u8]) -> Result<(), String> {
+	let arch = current_arch();
+	let exports = if bytes.starts_with(b"\x7fELF") {
+		check_elf(bytes, arch.as_ref())?
+	} else if bytes.starts_with(b"MZ") {
+		check_pe(bytes, arch.as_ref())?
+	} else {
+		return Err("is neither an ELF shared library nor a Windows DLL".to_string());
+	};
+
+	let missing: Vec<&str> = REQUIRED_EXPORTS.iter().copied().filter(|name| !exports.contains(name)).collect();
+	if !missing.is_empty() {
+		return Err(format!("does not export {}", missing.join(", ")));
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn put(out: &mut Vec<u8>, offset: usize, bytes: &[u8]) {
+		if out.len() < offset + bytes.len() {
+			out.resize(offset + bytes.len(), 0);
+		}
+		out[offset..offset + bytes.len()].copy_from_slice(bytes);
+	}
+
+	fn put_word(out: &mut Vec<u8>, offset: usize, value: usize, wide: bool) {
+		if wide {
+			put(out, offset, &(value as u64).to_le_bytes());
+		} else {
+			put(out, offset, &(value as u32).to_le_bytes());
+		}
+	}
+
+	// A minimal ELF shared library: header, .dynstr, .dynsym and three section headers
+	fn elf(wide: bool, machine: u16, exports: &[&str]) -> Vec<u8> {
+		let (header_size, symbol_size, section_size) = if wide { (64, 24, 64) } else { (52, 16, 40) };
+		let mut out = Vec::new();
+		put(&mut out, 0, b"\x7fELF");
+		put(&mut out, 4, &[if wide { 2 } else { 1 }, 1, 1]);
+		put(&mut out, 0x10, &3u16.to_le_bytes());
+		put(&mut out, 0x12, &machine.to_le_bytes());
+
+		let strings = header_size;
+		let mut names = vec![0u8];
+		let mut offsets = Vec::new();
+		for name in exports {
+			offsets.push(names.len());
+			names.extend_from_slice(name.as_bytes());
+			names.push(0);
+		}
+		put(&mut out, strings, &names);
+
+		// Symbol 0 is the reserved null symbol
+		let symbols = strings + names.len();
+		for (index, name) in offsets.iter().enumerate() {
+			let symbol = symbols + (index + 1) * symbol_size;
+			put(&mut out, symbol, &(*name as u32).to_le_bytes());
+			// STB_GLOBAL, defined in section 1
+			let (info, shndx) = if wide { (symbol + 4, symbol + 6) } else { (symbol + 12, symbol + 14) };
+			put(&mut out, info, &[0x12]);
+			put(&mut out, shndx, &1u16.to_le_bytes());
+		}
+		let symbols_len = (exports.len() + 1) * symbol_size;
+
+		let sections = symbols + symbols_len;
+		let fields: [usize; 4] = if wide { [0x18, 0x20, 0x28, 0x38] } else { [0x10, 0x14, 0x18, 0x24] };
+		let dynsym = sections + section_size;
+		put(&mut out, dynsym + 4, &11u32.to_le_bytes());
+		put_word(&mut out, dynsym + fields[0], symbols, wide);
+		put_word(&mut out, dynsym + fields[1], symbols_len, wide);
+		put(&mut out, dynsym + fields[2], &2u32.to_le_bytes());
+		put_word(&mut out, dynsym + fields[3], symbol_size, wide);
+		let dynstr = sections + 2 * section_size;
+		put(&mut out, dynstr + 4, &3u32.to_le_bytes());
+		put_word(&mut out, dynstr + fields[0], strings, wide);
+		put_word(&mut out, dynstr + fields[1], names.len(), wide);
+		put(&mut out, dynstr + section_size - 1, &[0]);
+
+		if wide {
+			put(&mut out, 0x28, &(sections as u64).to_le_bytes());
+			put(&mut out, 0x3a, &(section_size as u16).to_le_bytes());
+			put(&mut out, 0x3c, &3u16.to_le_bytes());
+		} else {
+			put(&mut out, 0x20, &(sections as u32).to_le_bytes());
+			put(&mut out, 0x2e, &(section_size as u16).to_le_bytes());
+			put(&mut out, 0x30, &3u16.to_le_bytes());
+		}
+		out
+	}
+
+	fn native() -> Arch {
+		current_arch().expect("tests run on a supported architecture")
+	}
+
+	#[test]
+	#[cfg(not(target_os = "windows"))]
+	fn accepts_matching_elf() {
+		let arch = native();
+		assert_eq!(check(&elf(arch.bits == 64, arch.elf_machine, REQUIRED_EXPORTS)), Ok(()));
+	}
+
+	#[test]
+	#[cfg(not(target_os = "windows"))]
+	fn rejects_wrong_machine_and_class() {
+		let arch = native();
+		let other_machine = if arch.elf_machine == 40 { 62 } else { 40 };
+		let error = check(&elf(arch.bits == 64, other_machine, REQUIRED_EXPORTS)).unwrap_err();
+		assert!(error.starts_with(&format!("is built for ELF machine {}", other_machine)), "{}", error);
+
+		let error = check(&elf(arch.bits != 64, arch.elf_machine, REQUIRED_EXPORTS)).unwrap_err();
+		assert!(error.starts_with("is built for ELF machine"), "{}", error);
+
+		let mut unknown_class = elf(arch.bits == 64, arch.elf_machine, REQUIRED_EXPORTS);
+		unknown_class[4] = 7;
+		assert_eq!(check(&unknown_class).unwrap_err(), "has an unknown ELF class");
+	}
+
+	#[test]
+	#[cfg(not(target_os = "windows"))]
+	fn rejects_missing_exports() {
+		let arch = native();
+		assert_eq!(check(&elf(arch.bits == 64, arch.elf_machine, &["gmod13_open"])).unwrap_err(), "does not export gmod13_close");
+	}
+
+	#[test]
+	fn rejects_truncated_files() {
+		let arch = native();
+		let full = elf(arch.bits == 64, arch.elf_machine, REQUIRED_EXPORTS);
+		for len in 0..full.len() {
+			assert!(check(&full[..len]).is_err(), "accepted {} of {} bytes", len, full.len());
+		}
+		assert!(check(b"MZ").is_err());
+	}
+
+	#[test]
+	fn rejects_html_error_pages() {
+		let page = b"<!DOCTYPE html>\n<html><head><title>404 Not Found</title></head><body>Not Found</body></html>";
+		assert_eq!(check(page).unwrap_err(), "is neither an ELF shared library nor a Windows DLL");
+		assert!(check(b"").is_err());
+	}
+}
@@ -0,0 +1,325 @@
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs};
+
+use crate::{handshake, print_log};
+
+const CONFIG_FILE: &str = "garrysmod/data/gm_integration/updater.json";
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+	pub addon: AddonConfig,
+	pub conflicts: ConflictConfig,
+	pub dependencies: Dependencies,
+	pub inventory: InventoryConfig,
+	pub gc: GcConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct GcConfig {
+	pub enabled: bool,
+	// Only leftovers older than this are removed, so a running update is never touched
+	pub max_age_hours: u64,
+	// Versioned copies of the integration binary to keep
+	pub keep_generations: usize,
+	// Remove binaries of this integration and its dependencies built for other platforms
+	pub other_platforms: bool,
+}
+
+impl Default for GcConfig {
+	fn default() -> Self {
+		GcConfig { enabled: true, max_age_hours: 24, keep_generations: 2, other_platforms: true }
+	}
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct InventoryConfig {
+	// Report on every module in lua/bin after the dependencies are updated
+	pub enabled: bool,
+}
+
+impl Default for InventoryConfig {
+	fn default() -> Self {
+		InventoryConfig { enabled: true }
+	}
+}
+
+// Binary modules kept up to date in garrysmod/lua/bin
+#[derive(Deserialize)]
+#[serde(transparent)]
+pub struct Dependencies(pub Vec<Dependency>);
+
+impl Default for Dependencies {
+	fn default() -> Self {
+		Dependencies(vec![
+			Dependency { id: "gwsockets".to_string(), repo: "FredyH/GWSockets".to_string(), ..Dependency::default() },
+			Dependency { id: "reqwest".to_string(), repo: "WilliamVenner/gmsv_reqwest".to_string(), ..Dependency::default() },
+		])
+	}
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct Dependency {
+	// Key in versions.json, also available as `{id}` in asset names
+	pub id: String,
+	// GitHub repository as `owner/name`
+	pub repo: String,
+	// URL of a GitHub-style release JSON to use instead of the GitHub API, e.g. a mirror
+	pub source: Option<String>,
+	// Asset per platform suffix (win32, win64, linux, linux64), `*` covers the others
+	pub assets: BTreeMap<String, AssetMatch>,
+	// Name of the installed file, defaults to the asset name or the binary's name in an archive
+	pub file: Option<String>,
+	// Path of the binary inside a zip or tar.gz asset, globs and placeholders allowed
+	pub binary: Option<String>,
+	// Other files to take from an archive asset, such as shared libraries next to srcds
+	pub extra_files: Vec<ExtraFile>,
+	// `latest`, a semver requirement, or a literal tag
+	pub version: String,
+	pub enabled: bool,
+	// What to do with a binary that is already in place but has no recorded version
+	pub unrecorded: UnrecordedPolicy,
+	// Allows `replace` to overwrite binaries that match no known release
+	pub replace_custom: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UnrecordedPolicy {
+	// Record the matching release and manage the binary from then on
+	Adopt,
+	// Never touch it
+	Leave,
+	// Install the selected release over it
+	Replace,
+}
+
+impl Default for Dependency {
+	fn default() -> Self {
+		Dependency {
+			id: String::new(),
+			repo: String::new(),
+			source: None,
+			assets: BTreeMap::from([("*".to_string(), AssetMatch::Name("gmsv_{id}_{suffix}.dll".to_string()))]),
+			file: None,
+			binary: None,
+			extra_files: Vec::new(),
+			version: "latest".to_string(),
+			enabled: true,
+			unrecorded: UnrecordedPolicy::Adopt,
+			replace_custom: false,
+		}
+	}
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ExtraFile {
+	// Path inside the archive, globs and placeholders allowed
+	pub from: String,
+	// Folder the matching files are placed in, relative to the server root
+	pub to: String,
+}
+
+// An asset name with `{id}` and `{suffix}` placeholders, or a regex over asset names
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum AssetMatch {
+	Name(String),
+	Regex { regex: String },
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct AddonConfig {
+	// Folder name under garrysmod/addons, also available as `{folder}` in rename rules
+	pub folder: String,
+	// Overrides the rules from the release manifest when set
+	pub renames: Option<Vec<RenameRule>>,
+	// Name of the optional JSON release asset describing how to install the addon
+	pub manifest_asset: String,
+	// Prebuilt addon zip preferred over GitHub's source archive
+	pub addon_asset: String,
+	// What to do when the installed files no longer match the install manifest
+	pub verify: VerifyMode,
+	// Install as a loose folder or as a single packed .gma
+	pub format: InstallFormat,
+	// Release asset to install instead of the source archive, for addons published as .gma
+	pub gma_asset: Option<String>,
+	// Whether a .gma release asset is installed untouched or extracted like a source archive
+	pub gma_install: GmaInstall,
+	// Globs over release paths, a file is installed when it matches `include` and not `exclude`
+	pub include: Vec<String>,
+	pub exclude: Vec<String>,
+	// Globs of installed files operators edit, added to the ones the release manifest declares
+	pub user_owned: Vec<String>,
+	// Installs from a git repository instead of GitHub releases
+	pub git: Option<GitSource>,
+}
+
+impl Default for AddonConfig {
+	fn default() -> Self {
+		AddonConfig {
+			folder: "_gmod_integration_latest".to_string(),
+			renames: None,
+			manifest_asset: "gmod-integration-manifest.json".to_string(),
+			addon_asset: "gmod-integration-addon.zip".to_string(),
+			verify: VerifyMode::Report,
+			format: InstallFormat::Folder,
+			gma_asset: None,
+			gma_install: GmaInstall::Extract,
+			include: vec!["**".to_string()],
+			exclude: default_excludes(),
+			user_owned: Vec::new(),
+			git: None,
+		}
+	}
+}
+
+// Repository and documentation files that have no use on a server
+pub fn default_excludes() -> Vec<String> {
+	[
+		".git/**",
+		".github/**",
+		".gitlab-ci.yml",
+		".gitignore",
+		".gitattributes",
+		".editorconfig",
+		".vscode/**",
+		"**/*.md",
+		"LICENSE*",
+		"docs/**",
+		"test/**",
+		"tests/**",
+	]
+	.iter()
+	.map(|pattern| pattern.to_string())
+	.collect()
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallFormat {
+	Folder,
+	Gma,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct GitSource {
+	// URL of a remote repository, fetched into a cache on every check, or the path to a local
+	// repository, bare or not, which is read in place
+	pub repo: String,
+	// Branch, tag or commit to install
+	#[serde(rename = "ref", default = "default_git_ref")]
+	pub reference: String,
+}
+
+fn default_git_ref() -> String {
+	"HEAD".to_string()
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GmaInstall {
+	AsIs,
+	Extract,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum VerifyMode {
+	Off,
+	Report,
+	Repair,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+	Warn,
+	Disable,
+	Migrate,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ConflictConfig {
+	pub policy: ConflictPolicy,
+	// Addon folder, .gma and addon.json title names that identify a copy of the integration,
+	// compared whole and ignoring case, `-` and spaces
+	pub names: Vec<String>,
+	// Paths that only a copy of the integration ships, relative to an addon root
+	pub signature_paths: Vec<String>,
+	// Files copied from a conflicting copy into the installed addon by the `migrate` policy
+	pub migrate_paths: Vec<String>,
+	// Where the `disable` and `migrate` policies move conflicting addon folders
+	pub disabled_dir: String,
+}
+
+impl Default for ConflictConfig {
+	fn default() -> Self {
+		ConflictConfig {
+			policy: ConflictPolicy::Warn,
+			names: vec!["gmod_integration".to_string()],
+			signature_paths: vec![
+				"lua/autorun/gmod_integration.lua".to_string(),
+				"lua/gmod_integration".to_string(),
+			],
+			migrate_paths: Vec::new(),
+			disabled_dir: "garrysmod/addons_disabled".to_string(),
+		}
+	}
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RenameRule {
+	pub from: String,
+	pub to: String,
+	// Optional rules don't warn when `from` is missing from the release
+	#[serde(default)]
+	pub optional: bool,
+}
+
+impl RenameRule {
+	fn new(from: &str, to: &str, optional: bool) -> Self {
+		RenameRule { from: from.to_string(), to: to.to_string(), optional }
+	}
+}
+
+pub fn default_renames() -> Vec<RenameRule> {
+	vec![
+		RenameRule::new("lua/autorun/gmod_integration.lua", "lua/autorun/{folder}.lua", false),
+		RenameRule::new("gmod_integration", "{folder}", true),
+		RenameRule::new("lua/gmod_integration", "lua/{folder}", false),
+	]
+}
+
+// Install instructions published alongside a release
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ReleaseManifest {
+	pub renames: Option<Vec<RenameRule>>,
+	// Installed paths (after renames, `{folder}` allowed) that operators are expected to edit
+	pub user_owned: Vec<String>,
+}
+
+pub fn load_config() -> Config {
+	// A loader that did the handshake already read the file
+	let content = match handshake::loader().and_then(|loader| loader.config_json.clone()) {
+		Some(content) => content,
+		None => match fs::read_to_string(CONFIG_FILE) {
+			Ok(content) => content,
+			Err(_) => return Config::default(),
+		},
+	};
+
+	match serde_json::from_str(&content) {
+		Ok(config) => config,
+		Err(e) => {
+			print_log(&format!("Invalid {}, using defaults: {}", CONFIG_FILE, e));
+			Config::default()
+		}
+	}
+}
@@ -0,0 +1,221 @@
+use chrono::Local;
+use serde::Deserialize;
+use std::{
+	fs,
+	path::{Path, PathBuf},
+};
+
+use crate::{
+	ADDONS_DIR,
+	config::{self, Config, ConflictPolicy},
+	copy_dir, gma, manifest, move_dir, print_log, rename,
+};
+
+// The garrysmod folder is laid out like an addon root, so signature paths apply to both
+const GAME_DIR: &str = "garrysmod";
+
+struct Conflict {
+	path: PathBuf,
+	reason: String,
+	// Root to migrate files from, None when the copy is packed
+	root: Option<PathBuf>,
+	// Whether it ships a signature path. Names alone never get a copy moved
+	signed: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct AddonInfo {
+	title: String,
+}
+
+fn normalize(name: &str) -> String {
+	name.to_lowercase().replace(['-', ' '], "_")
+}
+
+// Whole names only, so other addons named after the integration don't match
+fn matches_name(name: &str, names: &[String]) -> bool {
+	let name = normalize(name);
+	names.iter().any(|candidate| name == normalize(candidate))
+}
+
+fn inspect_addon_folder(path: &Path, name: &str, config: &Config) -> Option<Conflict> {
+	let signature = config.conflicts.signature_paths.iter().find(|sig| path.join(sig).exists());
+	let conflict = |reason: String| Some(Conflict { path: path.to_path_buf(), reason, root: Some(path.to_path_buf()), signed: signature.is_some() });
+
+	if matches_name(name, &config.conflicts.names) {
+		return conflict("folder name".to_string());
+	}
+
+	if let Some(signature) = signature {
+		return conflict(format!("contains {}", signature));
+	}
+
+	let info: AddonInfo = fs::read_to_string(path.join("addon.json"))
+		.ok()
+		.and_then(|content| serde_json::from_str(&content).ok())
+		.unwrap_or_default();
+	if !info.title.is_empty() && matches_name(&info.title, &config.conflicts.names) {
+		return conflict(format!("addon.json title \"{}\"", info.title));
+	}
+
+	None
+}
+
+fn inspect_packed(path: &Path, name: &str, config: &Config) -> Option<Conflict> {
+	let stem = name.strip_suffix(".gma").unwrap_or(name);
+	if !matches_name(stem, &config.conflicts.names) {
+		return None;
+	}
+
+	let entries = gma::list(path).unwrap_or_default();
+	let signed = config.conflicts.signature_paths.iter().any(|sig| entries.iter().any(|entry| entry == Path::new(sig)));
+	Some(Conflict { path: path.to_path_buf(), reason: "packed addon name".to_string(), root: None, signed })
+}
+
+fn find_conflicts(config: &Config) -> Vec<Conflict> {
+	let mut conflicts = Vec::new();
+	let own_gma = format!("{}.gma", config.addon.folder);
+
+	if let Ok(entries) = fs::read_dir(ADDONS_DIR) {
+		for entry in entries.flatten() {
+			let path = entry.path();
+			let name = entry.file_name().to_string_lossy().into_owned();
+			if name == config.addon.folder || name == own_gma {
+				continue;
+			}
+
+			let conflict = if path.is_dir() {
+				inspect_addon_folder(&path, &name, config)
+			} else if path.extension().and_then(|ext| ext.to_str()) == Some("gma") {
+				inspect_packed(&path, &name, config)
+			} else {
+				None
+			};
+			conflicts.extend(conflict);
+		}
+	}
+
+	// Copies merged straight into garrysmod/lua
+	for signature in &config.conflicts.signature_paths {
+		let path = Path::new(GAME_DIR).join(signature);
+		if signature.starts_with("lua/") && path.exists() {
+			conflicts.push(Conflict {
+				path,
+				reason: "loose file in garrysmod/lua".to_string(),
+				root: Some(PathBuf::from(GAME_DIR)),
+				signed: true,
+			});
+		}
+	}
+
+	conflicts
+}
+
+fn disable(conflict: &Conflict, config: &Config) -> Result<PathBuf, String> {
+	let file_name = conflict.path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+
+	// Loose Lua paths stop loading once renamed, addons have to leave the addons folder
+	let mut target = if conflict.path.starts_with(ADDONS_DIR) {
+		Path::new(&config.conflicts.disabled_dir).join(&file_name)
+	} else {
+		conflict.path.with_file_name(format!("{}.disabled", file_name))
+	};
+	if target.exists() {
+		let stamp = Local::now().format("%Y%m%d-%H%M%S");
+		target = target.with_file_name(format!("{}.{}", target.file_name().unwrap_or_default().to_string_lossy(), stamp));
+	}
+
+	if let Some(parent) = target.parent() {
+		fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+	}
+
+	if conflict.path.is_dir() {
+		move_dir(&conflict.path, &target).map_err(|e| e.to_string())?;
+	} else {
+		fs::rename(&conflict.path, &target).map_err(|e| e.to_string())?;
+	}
+	Ok(target)
+}
+
+fn migrate(conflict: &Conflict, config: &Config, addon_dir: &Path) {
+	let Some(root) = &conflict.root else {
+		print_log(&format!("Nothing to migrate from packed addon {}", conflict.path.display()));
+		return;
+	};
+
+	if config.conflicts.migrate_paths.is_empty() {
+		print_log("No migrate_paths configured, nothing to migrate");
+		return;
+	}
+
+	let rules = config.addon.renames.clone().unwrap_or_else(config::default_renames);
+	let rules = rename::resolve_rules(&rules, &config.addon.folder);
+
+	let mut migrated = Vec::new();
+	for relative in &config.conflicts.migrate_paths {
+		let from = root.join(relative);
+		if !from.exists() {
+			continue;
+		}
+
+		let mapped = rename::map_path(relative, &rules);
+		let to = addon_dir.join(&mapped);
+		if let Some(parent) = to.parent() {
+			let _ = fs::create_dir_all(parent);
+		}
+
+		let result = if from.is_dir() { copy_dir(&from, &to) } else { fs::copy(&from, &to).map(|_| ()) };
+		match result {
+			Ok(()) => {
+				print_log(&format!("Migrated {} to {}", from.display(), to.display()));
+				migrated.push(mapped);
+			}
+			Err(e) => print_log(&format!("Failed to migrate {}: {}", from.display(), e)),
+		}
+	}
+
+	// Migrated files are wanted local changes, not tampering
+	manifest::refresh(addon_dir, &migrated);
+}
+
+// Looks for other installs of the integration that would load next to ours
+pub fn resolve(config: &Config) {
+	let conflicts = find_conflicts(config);
+	if conflicts.is_empty() {
+		return;
+	}
+
+	let addon_dir = Path::new(ADDONS_DIR).join(&config.addon.folder);
+
+	for conflict in &conflicts {
+		print_log(&format!(
+			"Warning: another copy of Gmod Integration found at {} ({})",
+			conflict.path.display(),
+			conflict.reason
+		));
+
+		match config.conflicts.policy {
+			ConflictPolicy::Warn => continue,
+			_ if !conflict.signed => {
+				print_log("It contains none of the signature_paths, leaving it in place");
+				continue;
+			}
+			ConflictPolicy::Migrate if addon_dir.exists() => migrate(conflict, config, &addon_dir),
+			ConflictPolicy::Migrate => {
+				print_log("No installed addon folder to migrate into, leaving the other copy in place");
+				continue;
+			}
+			ConflictPolicy::Disable => {}
+		}
+
+		match disable(conflict, config) {
+			Ok(target) => print_log(&format!("Disabled {} (moved to {})", conflict.path.display(), target.display())),
+			Err(e) => print_log(&format!("Failed to disable {}: {}", conflict.path.display(), e)),
+		}
+	}
+
+	if config.conflicts.policy == ConflictPolicy::Warn {
+		print_log("Set conflicts.policy to \"disable\" or \"migrate\" to stop loading these copies");
+	}
+}
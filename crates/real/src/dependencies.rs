@@ -0,0 +1,480 @@
+use flate2::read::GzDecoder;
+use regex::Regex;
+use reqwest::blocking::Client;
+use semver::{Version, VersionReq};
+use serde::Deserialize;
+use std::{
+	collections::BTreeMap,
+	fs,
+	io::{Cursor, Read},
+	path::{Path, PathBuf},
+	time::Duration,
+};
+use zip::ZipArchive;
+
+use crate::{
+	Asset, BIN_DIR, Release,
+	config::{AssetMatch, Dependency, UnrecordedPolicy},
+	delta,
+	filter::glob_match,
+	get_platform_suffix, manifest, print_log,
+};
+
+// Releases whose asset is downloaded and hashed to identify an unrecorded binary, when there
+// is no published digest to compare with or the binary sits inside an archive
+const IDENTIFY_DOWNLOADS: usize = 3;
+
+// Dependency binaries are only checked against their published hash
+const DELTA: delta::Options = delta::Options { user_agent: "Gmod-Integration-Updater", log: print_log, check: |_| Ok(()) };
+
+fn expand_for(template: &str, dependency: &Dependency, suffix: &str) -> String {
+	template.replace("{id}", &dependency.id.to_lowercase()).replace("{suffix}", suffix)
+}
+
+fn expand(template: &str, dependency: &Dependency) -> String {
+	expand_for(template, dependency, get_platform_suffix())
+}
+
+// A release JSON, or a list of them from the releases endpoint or a mirror
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ReleaseFeed {
+	List(Vec<Release>),
+	Single(Release),
+}
+
+fn fetch_feed(client: &Client, url: &str) -> Result<Vec<Release>, Box<dyn std::error::Error>> {
+	let feed: ReleaseFeed = client
+		.get(url)
+		.header("User-Agent", "Gmod-Integration-Updater")
+		.timeout(Duration::from_secs(30))
+		.send()?
+		.error_for_status()?
+		.json()?;
+
+	Ok(match feed {
+		ReleaseFeed::List(releases) => releases,
+		ReleaseFeed::Single(release) => vec![release],
+	})
+}
+
+// Reads release tags like `v1.2.3`, `1.2` or `3` as semver versions
+fn parse_tag(tag: &str) -> Option<Version> {
+	let tag = tag.trim_start_matches(['v', 'V']);
+	Version::parse(tag)
+		.or_else(|_| Version::parse(&format!("{}.0", tag)))
+		.or_else(|_| Version::parse(&format!("{}.0.0", tag)))
+		.ok()
+}
+
+fn is_latest(dependency: &Dependency) -> bool {
+	matches!(dependency.version.as_str(), "latest" | "*" | "")
+}
+
+// The semver requirement in `version`, None for `latest` and literal tags
+pub fn requirement(dependency: &Dependency) -> Option<VersionReq> {
+	VersionReq::parse(&dependency.version).ok().filter(|_| !is_latest(dependency))
+}
+
+// Recent releases of the dependency, from the GitHub API or its source
+fn fetch_releases(client: &Client, dependency: &Dependency) -> Result<Vec<Release>, Box<dyn std::error::Error>> {
+	let url = match &dependency.source {
+		Some(source) => source.clone(),
+		None => format!("https://api.github.com/repos/{}/releases?per_page=100", dependency.repo),
+	};
+	fetch_feed(client, &url)
+}
+
+// Release lists fetched during one start, shared by the update and the inventory so each
+// dependency costs a single unauthenticated API call
+#[derive(Default)]
+pub struct ReleaseCache(BTreeMap<String, Result<Vec<Release>, String>>);
+
+impl ReleaseCache {
+	pub fn get(&mut self, client: &Client, dependency: &Dependency) -> Result<&[Release], String> {
+		self.0
+			.entry(dependency.id.clone())
+			.or_insert_with(|| fetch_releases(client, dependency).map_err(|e| e.to_string()))
+			.as_deref()
+			.map_err(|e| e.clone())
+	}
+}
+
+// Stable releases with a semver tag, newest first
+pub fn sorted_releases(releases: &[Release]) -> Vec<(Version, &Release)> {
+	let mut sorted: Vec<(Version, &Release)> = releases
+		.iter()
+		.filter(|release| !release.draft && !release.prerelease)
+		.filter_map(|release| parse_tag(&release.tag_name).map(|version| (version, release)))
+		.collect();
+	sorted.sort_by(|a, b| b.0.cmp(&a.0));
+	sorted
+}
+
+// Picks the newest release allowed by the dependency's version and
+// reports newer ones the constraint leaves out
+fn select_release(client: &Client, dependency: &Dependency, releases: &[Release]) -> Result<Release, Box<dyn std::error::Error>> {
+	let Some(requirement) = requirement(dependency) else {
+		// Without a semver requirement the version is `latest` or a literal tag. GitHub's latest
+		// release is the newest one that is neither a draft nor a prerelease
+		let latest = is_latest(dependency);
+		let found = releases.iter().find(|release| {
+			if latest {
+				dependency.source.is_some() || (!release.draft && !release.prerelease)
+			} else {
+				release.tag_name == dependency.version
+			}
+		});
+		if let Some(release) = found {
+			return Ok(release.clone());
+		}
+
+		// A tag older than the fetched list
+		if !latest && dependency.source.is_none() {
+			let url = format!("https://api.github.com/repos/{}/releases/tags/{}", dependency.repo, dependency.version);
+			if let Some(release) = fetch_feed(client, &url)?.into_iter().find(|release| release.tag_name == dependency.version) {
+				return Ok(release);
+			}
+		}
+		return Err(format!("No release {} found for {}", dependency.version, dependency.id).into());
+	};
+
+	let candidates = sorted_releases(releases);
+
+	let newest = candidates.first().map(|(version, release)| (version.clone(), release.tag_name.clone()));
+	let (version, release) = candidates
+		.into_iter()
+		.find(|(version, _)| requirement.matches(version))
+		.ok_or_else(|| format!("No release of {} matches {}", dependency.id, requirement))?;
+
+	if let Some((newest, tag)) = newest
+		&& newest > version
+	{
+		print_log(&format!(
+			"{} {} is available but excluded by the version constraint {}, staying on {}",
+			dependency.id, tag, requirement, release.tag_name
+		));
+	}
+
+	Ok(release.clone())
+}
+
+// Picks this platform's asset out of a release
+fn find_asset<'a>(dependency: &Dependency, release: &'a Release) -> Result<Option<&'a Asset>, String> {
+	let suffix = get_platform_suffix();
+	let Some(pattern) = dependency.assets.get(suffix).or_else(|| dependency.assets.get("*")) else {
+		return Ok(None);
+	};
+
+	match pattern {
+		AssetMatch::Name(template) => {
+			let name = expand(template, dependency);
+			Ok(release.assets.iter().find(|asset| asset.name == name))
+		}
+		AssetMatch::Regex { regex } => {
+			let regex = Regex::new(regex).map_err(|e| format!("Invalid asset regex for {}: {}", dependency.id, e))?;
+			Ok(release.assets.iter().find(|asset| regex.is_match(&asset.name)))
+		}
+	}
+}
+
+// Downloads an asset and checks it against the digest GitHub publishes for it
+fn fetch_asset(client: &Client, asset: &Asset) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+	let mut resp = client
+		.get(&asset.browser_download_url)
+		.header("User-Agent", "Gmod-Integration-Updater")
+		.timeout(Duration::from_secs(30))
+		.send()?;
+
+	// Check if response is successful
+	if !resp.status().is_success() {
+		return Err(format!("HTTP error: {}", resp.status()).into());
+	}
+
+	let mut bytes = Vec::new();
+	resp.read_to_end(&mut bytes)?;
+
+	// Verify something was downloaded and that it is what the release published
+	if bytes.is_empty() {
+		return Err("Downloaded file is empty".into());
+	}
+	if let Some(expected) = delta::expected_sha256(asset)
+		&& manifest::hash_bytes(&bytes) != expected
+	{
+		return Err(format!("{} does not match its published hash", asset.name).into());
+	}
+
+	Ok(bytes)
+}
+
+// Writes to a temporary file then renames it for atomic placement
+fn place(path: &Path, content: &[u8]) -> std::io::Result<()> {
+	if let Some(parent) = path.parent() {
+		fs::create_dir_all(parent)?;
+	}
+
+	let tmp_path = path.with_extension("tmp");
+	if let Err(e) = fs::write(&tmp_path, content).and_then(|_| fs::rename(&tmp_path, path)) {
+		let _ = fs::remove_file(&tmp_path);
+		return Err(e);
+	}
+	Ok(())
+}
+
+// Regular files of a zip or tar.gz archive as `/`-separated paths with their content
+fn read_archive(bytes: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+	let mut entries = Vec::new();
+
+	if bytes.starts_with(b"PK\x03\x04") {
+		let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(|e| format!("Failed to read zip archive: {}", e))?;
+		for i in 0..archive.len() {
+			let mut file = archive.by_index(i).map_err(|e| format!("Failed to read zip entry {}: {}", i, e))?;
+			if file.is_dir() {
+				continue;
+			}
+			let mut content = Vec::new();
+			file.read_to_end(&mut content).map_err(|e| format!("Failed to read {}: {}", file.name(), e))?;
+			entries.push((file.name().to_string(), content));
+		}
+	} else if bytes.starts_with(&[0x1f, 0x8b]) {
+		let mut archive = tar::Archive::new(GzDecoder::new(bytes));
+		for entry in archive.entries().map_err(|e| format!("Failed to read tar archive: {}", e))? {
+			let mut entry = entry.map_err(|e| format!("Failed to read tar entry: {}", e))?;
+			if !entry.header().entry_type().is_file() {
+				continue;
+			}
+			let path = entry.path().map_err(|e| format!("Invalid tar entry path: {}", e))?;
+			let name = path.to_string_lossy().trim_start_matches("./").to_string();
+			let mut content = Vec::new();
+			entry.read_to_end(&mut content).map_err(|e| format!("Failed to read {}: {}", name, e))?;
+			entries.push((name, content));
+		}
+	} else {
+		return Err("Asset is neither a zip nor a tar.gz archive".to_string());
+	}
+
+	Ok(entries)
+}
+
+fn file_name(path: &str) -> &str {
+	path.rsplit('/').next().unwrap_or(path)
+}
+
+// Name the binary is installed under
+fn installed_name(dependency: &Dependency, asset: &Asset) -> Result<String, String> {
+	if let Some(file) = &dependency.file {
+		return Ok(expand(file, dependency));
+	}
+
+	match &dependency.binary {
+		None => Ok(asset.name.clone()),
+		Some(binary) => {
+			let name = expand(file_name(binary), dependency);
+			if name.contains('*') {
+				return Err(format!("{} needs a `file` name because its binary path has wildcards", dependency.id));
+			}
+			Ok(name)
+		}
+	}
+}
+
+// Whether `name` is the file this dependency installs on the platform `suffix`
+pub fn installs_as(dependency: &Dependency, name: &str, suffix: &str) -> bool {
+	if let Some(file) = dependency.file.as_ref().or(dependency.binary.as_ref()) {
+		return glob_match(&expand_for(file_name(file), dependency, suffix), name);
+	}
+
+	match dependency.assets.get(suffix).or_else(|| dependency.assets.get("*")) {
+		Some(AssetMatch::Name(template)) => expand_for(template, dependency, suffix) == name,
+		Some(AssetMatch::Regex { regex }) => Regex::new(regex).is_ok_and(|regex| regex.is_match(name)),
+		None => false,
+	}
+}
+
+// The single entry of an archive that matches the dependency's `binary`
+fn find_binary<'a>(dependency: &Dependency, entries: &'a [(String, Vec<u8>)], binary: &str) -> Result<&'a [u8], String> {
+	let pattern = expand(binary, dependency);
+	let matches: Vec<_> = entries.iter().filter(|(path, _)| glob_match(&pattern, path)).collect();
+	match matches.as_slice() {
+		[(_, content)] => Ok(content),
+		[] => Err(format!("No file matching {} in the archive", pattern)),
+		_ => Err(format!("{} matches {} files in the archive", pattern, matches.len())),
+	}
+}
+
+// Installs the binary and the extra files of an archive asset
+fn install_from_archive(dependency: &Dependency, bytes: &[u8], binary: &str, installed: &Path) -> Result<(), String> {
+	let entries = read_archive(bytes)?;
+	let content = find_binary(dependency, &entries, binary)?;
+
+	place(installed, content).map_err(|e| format!("Failed to install {}: {}", installed.display(), e))?;
+
+	for extra in &dependency.extra_files {
+		let pattern = expand(&extra.from, dependency);
+		let mut placed = 0;
+
+		for (path, content) in entries.iter().filter(|(path, _)| glob_match(&pattern, path)) {
+			let out_path = Path::new(&extra.to).join(file_name(path));
+			place(&out_path, content).map_err(|e| format!("Failed to install {}: {}", out_path.display(), e))?;
+			print_log(&format!("Installed {} to {}", path, out_path.display()));
+			placed += 1;
+		}
+
+		if placed == 0 {
+			print_log(&format!("Warning: no file matching {} in the archive", pattern));
+		}
+	}
+
+	Ok(())
+}
+
+// Hash of the binary a release asset installs
+fn binary_hash(client: &Client, dependency: &Dependency, asset: &Asset) -> Result<String, Box<dyn std::error::Error>> {
+	let bytes = fetch_asset(client, asset)?;
+	Ok(match &dependency.binary {
+		Some(binary) => manifest::hash_bytes(find_binary(dependency, &read_archive(&bytes)?, binary)?),
+		None => manifest::hash_bytes(&bytes),
+	})
+}
+
+// Finds the release that installs the same binary as `installed`: by the digest GitHub
+// publishes for the asset, or by downloading the asset of the most recent releases when
+// there is none or the binary comes out of an archive
+fn identify(client: &Client, dependency: &Dependency, installed: &Path, releases: &[Release]) -> Result<Option<String>, Box<dyn std::error::Error>> {
+	let hash = manifest::hash_file(installed)?.sha256;
+	let mut downloads = 0;
+
+	for release in releases {
+		let Some(asset) = find_asset(dependency, release)? else {
+			continue;
+		};
+
+		let published = delta::expected_sha256(asset).filter(|_| dependency.binary.is_none());
+		let matches = match published {
+			Some(published) => published == hash,
+			None if downloads < IDENTIFY_DOWNLOADS => {
+				downloads += 1;
+				print_log(&format!("Downloading {} of {} to identify {}", asset.name, release.tag_name, installed.display()));
+				match binary_hash(client, dependency, asset) {
+					Ok(binary) => binary == hash,
+					Err(e) => {
+						print_log(&format!("Failed to check {} of {}: {}", asset.name, release.tag_name, e));
+						false
+					}
+				}
+			}
+			None => false,
+		};
+		if matches {
+			return Ok(Some(release.tag_name.clone()));
+		}
+	}
+
+	Ok(None)
+}
+
+// Installs the release selected by the dependency's version, returns the new tag when something changed
+fn update(
+	client: &Client,
+	dependency: &Dependency,
+	current_version: Option<&String>,
+	cache: &mut ReleaseCache,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+	// Fetch release information for dependency
+	let releases = cache.get(client, dependency)?;
+	let release = select_release(client, dependency, releases)?;
+
+	// Validate release data
+	if release.tag_name.is_empty() {
+		return Err(format!("Invalid release for {}: empty tag name", dependency.id).into());
+	}
+
+	let Some(asset) = find_asset(dependency, &release)? else {
+		print_log(&format!("No matching asset found for {} on {}", dependency.id, get_platform_suffix()));
+		return Ok(None);
+	};
+
+	let installed = PathBuf::from(BIN_DIR).join(installed_name(dependency, asset)?);
+
+	// A binary nobody recorded was put there by hand, find out what it is before touching it
+	let mut current_version = current_version.cloned();
+	if current_version.is_none() && installed.exists() {
+		let known = identify(client, dependency, &installed, releases)?;
+		let name = installed.display();
+
+		match (&known, dependency.unrecorded) {
+			(_, UnrecordedPolicy::Leave) => {
+				print_log(&format!("Leaving unrecorded {} alone ({})", name, known.as_deref().unwrap_or("unknown build")));
+				return Ok(None);
+			}
+			(Some(tag), UnrecordedPolicy::Adopt) => {
+				print_log(&format!("Adopted existing {} as release {}", name, tag));
+				if tag == &release.tag_name {
+					return Ok(known);
+				}
+				current_version = known;
+			}
+			(Some(tag), UnrecordedPolicy::Replace) => print_log(&format!("Replacing existing {} (release {})", name, tag)),
+			(None, UnrecordedPolicy::Replace) if dependency.replace_custom => {
+				print_log(&format!("Replacing custom build {}", name));
+			}
+			(None, _) => {
+				print_log(&format!(
+					"{} matches no known release of {}, leaving the custom build in place. Set replace_custom with the replace policy to overwrite it",
+					name, dependency.id
+				));
+				return Ok(None);
+			}
+		}
+	}
+	let current_version = current_version.as_ref();
+
+	// Skip if already up to date
+	if let Some(current) = current_version
+		&& current == &release.tag_name
+		&& installed.exists()
+	{
+		print_log(&format!("{} is up to date ({})", dependency.id, release.tag_name));
+		return Ok(None);
+	}
+
+	// Prefer a small delta against the installed binary when the release ships one
+	if dependency.binary.is_none()
+		&& let Some(current) = current_version
+		&& installed.exists()
+		&& delta::try_update(client, &release.assets, asset, &installed, current, &installed, &DELTA)
+	{
+		return Ok(Some(release.tag_name));
+	}
+
+	let bytes = fetch_asset(client, asset).inspect_err(|e| print_log(&format!("Failed to download {}: {}", asset.name, e)))?;
+	match &dependency.binary {
+		Some(binary) => install_from_archive(dependency, &bytes, binary, &installed)?,
+		None => place(&installed, &bytes)?,
+	}
+
+	print_log(&format!("Downloaded {}", asset.name));
+	Ok(Some(release.tag_name))
+}
+
+// Brings every enabled dependency up to date, failures are logged and don't stop the others
+pub fn update_all(client: &Client, dependencies: &[Dependency], versions: &mut BTreeMap<String, String>, cache: &mut ReleaseCache) {
+	for dependency in dependencies {
+		if !dependency.enabled {
+			continue;
+		}
+		if dependency.id.is_empty() || (dependency.repo.is_empty() && dependency.source.is_none()) {
+			print_log("Skipping dependency without id or repo");
+			continue;
+		}
+
+		match update(client, dependency, versions.get(&dependency.id), cache) {
+			Ok(Some(new_version)) => {
+				print_log(&format!("{} is now at {}", dependency.id, new_version));
+				versions.insert(dependency.id.clone(), new_version);
+			}
+			Ok(None) => {} // Up to date
+			Err(e) => print_log(&format!("Failed to update {}: {}", dependency.id, e)),
+		}
+	}
+}
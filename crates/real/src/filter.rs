@@ -0,0 +1,99 @@
+use crate::gma::wildcard_match;
+
+// Matches a `/`-separated path against a glob where `*` stays within one segment
+// and a `**` segment spans any number of folders
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+	let pattern: Vec<&str> = pattern.split('/').collect();
+	let path: Vec<&str> = path.split('/').collect();
+	match_segments(&pattern, &path)
+}
+
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+	match pattern.split_first() {
+		None => path.is_empty(),
+		Some((&"**", rest)) => (0..=path.len()).any(|skip| match_segments(rest, &path[skip..])),
+		Some((segment, rest)) => path
+			.split_first()
+			.is_some_and(|(name, remaining)| wildcard_match(segment, name) && match_segments(rest, remaining)),
+	}
+}
+
+pub fn is_included(path: &str, include: &[String], exclude: &[String]) -> bool {
+	include.iter().any(|pattern| glob_match(pattern, path)) && !exclude.iter().any(|pattern| glob_match(pattern, path))
+}
+
+// Predicate the extractors ask before writing a file, so excluded files never reach the disk.
+// The paths it turns down are collected in `skipped`
+pub fn keep<'a>(include: &'a [String], exclude: &'a [String], skipped: &'a mut Vec<String>) -> impl FnMut(&str) -> bool + 'a {
+	move |path| {
+		let included = is_included(path, include, exclude);
+		if !included {
+			skipped.push(path.to_string());
+		}
+		included
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::config::default_excludes;
+
+	fn globs(patterns: &[&str]) -> Vec<String> {
+		patterns.iter().map(|pattern| pattern.to_string()).collect()
+	}
+
+	#[test]
+	fn default_excludes_drop_repository_files() {
+		let include = globs(&["**"]);
+		let exclude = default_excludes();
+		let cases = [
+			("lua/autorun/init.lua", true),
+			("materials/icon.png", true),
+			("lua/docs.lua", true),
+			("lua/tests.lua", true),
+			("README.md", false),
+			("lua/modules/README.md", false),
+			("LICENSE", false),
+			("LICENSE.txt", false),
+			(".git/HEAD", false),
+			(".github/workflows/ci.yml", false),
+			(".gitignore", false),
+			(".vscode/settings.json", false),
+			("docs/guide.txt", false),
+			("test/fixture.lua", false),
+			("tests/unit/parser.lua", false),
+		];
+		for (path, expected) in cases {
+			assert_eq!(is_included(path, &include, &exclude), expected, "{}", path);
+		}
+	}
+
+	#[test]
+	fn exclude_wins_over_include() {
+		let cases: [(&[&str], &[&str], &str, bool); 8] = [
+			(&["lua/**"], &[], "lua/autorun/init.lua", true),
+			(&["lua/**"], &[], "materials/icon.png", false),
+			(&["lua/**"], &["lua/dev/**"], "lua/dev/debug.lua", false),
+			(&["lua/**"], &["lua/dev/**"], "lua/autorun/init.lua", true),
+			(&["**"], &["**"], "lua/autorun/init.lua", false),
+			(&["lua/*.lua"], &[], "lua/autorun/init.lua", false),
+			(&["lua/*.lua", "materials/**"], &["**/*.psd"], "materials/icon.psd", false),
+			(&[], &[], "lua/autorun/init.lua", false),
+		];
+		for (include, exclude, path, expected) in cases {
+			assert_eq!(is_included(path, &globs(include), &globs(exclude)), expected, "{} {:?} {:?}", path, include, exclude);
+		}
+	}
+
+	#[test]
+	fn keep_collects_skipped_paths() {
+		let (include, exclude) = (globs(&["**"]), default_excludes());
+		let mut skipped = Vec::new();
+		let mut keep = keep(&include, &exclude, &mut skipped);
+		assert!(keep("lua/autorun/init.lua"));
+		assert!(!keep("README.md"));
+		drop(keep);
+		assert_eq!(skipped, vec!["README.md"]);
+	}
+}
@@ -0,0 +1,139 @@
+use regex::Regex;
+use std::{
+	cmp::Reverse,
+	fs,
+	path::{Path, PathBuf},
+	time::{Duration, SystemTime},
+};
+
+use crate::{
+	ADDONS_DIR, BACKUP_DIR, BIN_DIR, GMA_DOWNLOAD_PATH, STAGING_DIR, ZIP_DOWNLOAD_PATH,
+	config::Config,
+	dependencies, get_platform_suffix, manifest, print_log,
+};
+
+const PLATFORMS: &[&str] = &["win32", "win64", "linux", "linux64", "osx", "osx64"];
+
+fn is_older_than(path: &Path, age: Duration) -> bool {
+	fs::symlink_metadata(path)
+		.and_then(|metadata| metadata.modified())
+		.ok()
+		.and_then(|modified| SystemTime::now().duration_since(modified).ok())
+		.is_some_and(|elapsed| elapsed > age)
+}
+
+fn size_of(path: &Path) -> u64 {
+	if path.is_dir() {
+		manifest::list_files(path)
+			.iter()
+			.filter_map(|relative| fs::metadata(path.join(relative)).ok())
+			.map(|metadata| metadata.len())
+			.sum()
+	} else {
+		fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0)
+	}
+}
+
+fn files_in(dir: &str) -> Vec<PathBuf> {
+	fs::read_dir(dir)
+		.map(|entries| entries.flatten().map(|entry| entry.path()).filter(|path| path.is_file()).collect())
+		.unwrap_or_default()
+}
+
+fn file_name(path: &Path) -> String {
+	path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+// Interrupted downloads, extractions and installs
+fn leftovers() -> Vec<PathBuf> {
+	let mut paths: Vec<PathBuf> = files_in(BIN_DIR)
+		.into_iter()
+		.chain(files_in(ADDONS_DIR))
+		.filter(|path| path.extension().is_some_and(|ext| ext == "tmp"))
+		.collect();
+
+	paths.extend([ZIP_DOWNLOAD_PATH, GMA_DOWNLOAD_PATH, STAGING_DIR, BACKUP_DIR].iter().map(PathBuf::from));
+	paths
+}
+
+// Binaries of this integration and its dependencies built for another platform
+fn other_platform_binaries(config: &Config) -> Vec<PathBuf> {
+	let current = get_platform_suffix();
+	let others: Vec<&str> = PLATFORMS.iter().copied().filter(|suffix| *suffix != current).collect();
+
+	files_in(BIN_DIR)
+		.into_iter()
+		.filter(|path| {
+			let name = file_name(path);
+			// A regex asset pattern can claim the same file for several platforms
+			if config.dependencies.0.iter().any(|dependency| dependencies::installs_as(dependency, &name, current)) {
+				return false;
+			}
+			others.iter().any(|suffix| {
+				name == format!("gmsv_gmod_integration_{}.dll", suffix)
+					|| name == format!("gmsv_gmod_integration_loader_{}.dll", suffix)
+					|| config.dependencies.0.iter().any(|dependency| dependencies::installs_as(dependency, &name, suffix))
+			})
+		})
+		.collect()
+}
+
+// Versioned copies of the integration binary beyond the newest `keep`. The one the loader
+// runs is never a candidate, and once it is versioned the old fixed-name copy counts as a generation
+fn old_generations(keep: usize, active: Option<&str>) -> Vec<PathBuf> {
+	let suffix = get_platform_suffix();
+	let Ok(pattern) = Regex::new(&format!(r"^gmod_integration_.+_{}\.dll$", suffix)) else {
+		return Vec::new();
+	};
+	let legacy = format!("gmsv_gmod_integration_{}.dll", suffix);
+
+	let mut generations: Vec<(SystemTime, PathBuf)> = files_in(BIN_DIR)
+		.into_iter()
+		.filter(|path| {
+			let name = file_name(path);
+			active.is_some_and(|active| active != name) && (pattern.is_match(&name) || name == legacy)
+		})
+		.filter_map(|path| {
+			let modified = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok()?;
+			Some((modified, path))
+		})
+		.collect();
+	generations.sort_by_key(|(modified, _)| Reverse(*modified));
+
+	// The active copy is one of the generations to keep
+	generations.into_iter().skip(keep.saturating_sub(1)).map(|(_, path)| path).collect()
+}
+
+// Removes leftovers older than the configured age and logs the space reclaimed
+pub fn run(config: &Config, active: Option<&str>) {
+	let age = Duration::from_secs(config.gc.max_age_hours * 3600);
+
+	let mut candidates = leftovers();
+	if config.gc.other_platforms {
+		candidates.extend(other_platform_binaries(config));
+	}
+	candidates.extend(old_generations(config.gc.keep_generations, active));
+
+	let mut removed = 0;
+	let mut reclaimed = 0;
+	for path in candidates {
+		if !path.exists() || !is_older_than(&path, age) {
+			continue;
+		}
+
+		let size = size_of(&path);
+		let result = if path.is_dir() { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+		match result {
+			Ok(()) => {
+				print_log(&format!("Cleaned up {} ({} bytes)", path.display(), size));
+				removed += 1;
+				reclaimed += size;
+			}
+			Err(e) => print_log(&format!("Failed to clean up {}: {}", path.display(), e)),
+		}
+	}
+
+	if removed > 0 {
+		print_log(&format!("Cleanup removed {} item(s), reclaimed {} bytes", removed, reclaimed));
+	}
+}
@@ -0,0 +1,131 @@
+use std::{
+	fs,
+	path::{Path, PathBuf},
+	sync::atomic::AtomicBool,
+};
+
+use gix::remote::Direction;
+
+use crate::{config::GitSource, gma::safe_entry_path, print_log};
+
+// Bare repositories that remote sources are fetched into, one per URL
+const CACHE_DIR: &str = "garrysmod/data/gm_integration/git";
+// Where the configured ref lands in the cache repository
+const FETCHED_REF: &str = "refs/gm_integration/fetched";
+
+// URLs (`https://`, `ssh://`, `file://`, ...) and scp-like `user@host:path` are fetched,
+// anything else is a repository on disk that is read in place
+fn is_remote(repo: &str) -> bool {
+	repo.contains("://") || repo.split_once(':').is_some_and(|(host, _)| host.contains('@'))
+}
+
+fn cache_path(url: &str) -> PathBuf {
+	let name: String = url.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' }).collect();
+	Path::new(CACHE_DIR).join(name)
+}
+
+fn open(source: &GitSource) -> Result<gix::Repository, String> {
+	let path = if is_remote(&source.repo) { cache_path(&source.repo) } else { PathBuf::from(&source.repo) };
+	gix::open(&path).map_err(|e| format!("Failed to open git repository {}: {}", path.display(), e))
+}
+
+// Fetches the configured ref from the remote into the cache repository. Only the objects
+// missing from the cache are transferred
+fn fetch(source: &GitSource) -> Result<(), String> {
+	let path = cache_path(&source.repo);
+	let repo = if path.exists() {
+		gix::open(&path).map_err(|e| format!("Failed to open git cache {}: {}", path.display(), e))?
+	} else {
+		fs::create_dir_all(&path).map_err(|e| format!("Failed to create git cache {}: {}", path.display(), e))?;
+		gix::init_bare(&path).map_err(|e| format!("Failed to create git cache {}: {}", path.display(), e))?
+	};
+
+	let refspec = format!("+{}:{}", source.reference, FETCHED_REF);
+	let remote = repo
+		.remote_at(source.repo.as_str())
+		.map_err(|e| format!("Invalid git URL {}: {}", source.repo, e))?
+		.with_refspecs([refspec.as_str()], Direction::Fetch)
+		.map_err(|e| format!("Invalid git ref {}: {}", source.reference, e))?;
+
+	let outcome = remote
+		.connect(Direction::Fetch)
+		.map_err(|e| format!("Failed to connect to {}: {}", source.repo, e))?
+		.prepare_fetch(gix::progress::Discard, Default::default())
+		.map_err(|e| format!("Failed to fetch {} from {}: {}", source.reference, source.repo, e))?
+		.receive(gix::progress::Discard, &AtomicBool::new(false))
+		.map_err(|e| format!("Failed to fetch {} from {}: {}", source.reference, source.repo, e))?;
+
+	// Otherwise the ref fetched last time would be resolved instead
+	if !outcome.ref_map.mappings.iter().any(|mapping| mapping.local.as_ref().is_some_and(|local| local == FETCHED_REF)) {
+		return Err(format!("{} not found in {}", source.reference, source.repo));
+	}
+
+	print_log(&format!("Fetched {} from {}", source.reference, source.repo));
+	Ok(())
+}
+
+// Resolves the configured ref (branch, tag or commit) to a full commit SHA, fetching it first
+// when the repository is remote
+pub fn resolve(source: &GitSource) -> Result<String, String> {
+	let remote = is_remote(&source.repo);
+	if remote {
+		fetch(source)?;
+	}
+
+	let repo = open(source)?;
+	let spec = if remote { FETCHED_REF } else { source.reference.as_str() };
+	let commit = repo
+		.rev_parse_single(spec)
+		.map_err(|e| format!("Failed to resolve {}: {}", source.reference, e))?
+		.object()
+		.map_err(|e| format!("Failed to read {}: {}", source.reference, e))?
+		.peel_to_commit()
+		.map_err(|e| format!("{} does not point to a commit: {}", source.reference, e))?;
+
+	Ok(commit.id.to_string())
+}
+
+// Writes the files of the tree of `commit` that `keep` accepts into `dest` the way an archive
+// extraction would. Returns the number of files written
+pub fn checkout(source: &GitSource, commit: &str, dest: &Path, keep: &mut impl FnMut(&str) -> bool) -> Result<usize, String> {
+	let repo = open(source)?;
+	let tree = repo
+		.rev_parse_single(commit)
+		.map_err(|e| format!("Failed to find commit {}: {}", commit, e))?
+		.object()
+		.map_err(|e| format!("Failed to read {}: {}", commit, e))?
+		.peel_to_tree()
+		.map_err(|e| format!("Failed to read tree of {}: {}", commit, e))?;
+	let entries = tree.traverse().breadthfirst.files().map_err(|e| format!("Failed to walk tree of {}: {}", commit, e))?;
+
+	fs::create_dir_all(dest).map_err(|e| format!("Failed to create staging directory: {:?}", e))?;
+
+	let mut written = 0;
+	for entry in entries {
+		let name = entry.filepath.to_string();
+		if entry.mode.is_tree() {
+			continue;
+		}
+		if !entry.mode.is_blob() {
+			// Symlinks and submodules have no content of their own to install
+			print_log(&format!("Skipping {} (not a regular file)", name));
+			continue;
+		}
+
+		let relative = safe_entry_path(&name).ok_or_else(|| format!("Suspicious file path in git tree: {}", name))?;
+		if !keep(&name) {
+			continue;
+		}
+		let object = repo.find_object(entry.oid).map_err(|e| format!("Failed to read {}: {}", name, e))?;
+
+		let out_path = dest.join(relative);
+		if let Some(parent) = out_path.parent() {
+			fs::create_dir_all(parent).map_err(|e| format!("Failed to create directory: {}", e))?;
+		}
+		fs::write(&out_path, &object.data).map_err(|e| format!("Failed to extract file {}: {}", out_path.display(), e))?;
+		written += 1;
+	}
+
+	print_log(&format!("Checked out {} file(s) from {}", written, commit));
+	Ok(written)
+}
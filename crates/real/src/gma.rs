@@ -0,0 +1,448 @@
+use serde::{Deserialize, Serialize};
+use std::{
+	fs,
+	io::{self, BufWriter, Read, Write},
+	path::{Path, PathBuf},
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{manifest, print_log};
+
+// Garry's Mod addon archive, the format written by gmad.exe
+const IDENT: &[u8; 4] = b"GMAD";
+const VERSION: u8 = 3;
+
+// Paths gmad accepts in an addon, `*` matches any run of characters
+const WHITELIST: &[&str] = &[
+	"lua/*.lua",
+	"scenes/*.vcd",
+	"particles/*.pcf",
+	"resource/fonts/*.ttf",
+	"scripts/vehicles/*.txt",
+	"resource/localization/*/*.properties",
+	"maps/*.bsp",
+	"maps/*.lmp",
+	"maps/*.nav",
+	"maps/*.ain",
+	"maps/thumb/*.png",
+	"sound/*.wav",
+	"sound/*.mp3",
+	"sound/*.ogg",
+	"materials/*.vmt",
+	"materials/*.vtf",
+	"materials/*.png",
+	"materials/*.jpg",
+	"materials/*.jpeg",
+	"materials/colorcorrection/*.raw",
+	"models/*.mdl",
+	"models/*.vtx",
+	"models/*.phy",
+	"models/*.ani",
+	"models/*.vvd",
+	"gamemodes/*/*.txt",
+	"gamemodes/*/*.fgd",
+	"gamemodes/*/logo.png",
+	"gamemodes/*/icon24.png",
+	"gamemodes/*/gamemode/*.lua",
+	"gamemodes/*/entities/effects/*.lua",
+	"gamemodes/*/entities/weapons/*.lua",
+	"gamemodes/*/entities/entities/*.lua",
+	"gamemodes/*/backgrounds/*.png",
+	"gamemodes/*/backgrounds/*.jpg",
+	"gamemodes/*/backgrounds/*.jpeg",
+	"gamemodes/*/content/models/*.mdl",
+	"gamemodes/*/content/models/*.vtx",
+	"gamemodes/*/content/models/*.phy",
+	"gamemodes/*/content/models/*.ani",
+	"gamemodes/*/content/models/*.vvd",
+	"gamemodes/*/content/materials/*.vmt",
+	"gamemodes/*/content/materials/*.vtf",
+	"gamemodes/*/content/materials/*.png",
+	"gamemodes/*/content/materials/*.jpg",
+	"gamemodes/*/content/materials/*.jpeg",
+	"gamemodes/*/content/scenes/*.vcd",
+	"gamemodes/*/content/particles/*.pcf",
+	"gamemodes/*/content/resource/fonts/*.ttf",
+	"gamemodes/*/content/scripts/vehicles/*.txt",
+	"gamemodes/*/content/resource/localization/*/*.properties",
+	"gamemodes/*/content/maps/*.bsp",
+	"gamemodes/*/content/maps/*.nav",
+	"gamemodes/*/content/maps/*.ain",
+	"gamemodes/*/content/maps/thumb/*.png",
+	"gamemodes/*/content/sound/*.wav",
+	"gamemodes/*/content/sound/*.mp3",
+	"gamemodes/*/content/sound/*.ogg",
+	"data_static/*.txt",
+	"data_static/*.dat",
+	"data_static/*.json",
+	"data_static/*.xml",
+	"data_static/*.csv",
+	"shaders/*.vcs",
+];
+
+// The subset of addon.json that ends up in the archive header
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct AddonJson {
+	title: String,
+	description: String,
+	#[serde(rename = "type")]
+	kind: String,
+	tags: Vec<String>,
+	ignore: Vec<String>,
+}
+
+// gmad stores the addon.json metadata as a JSON blob in the description field
+#[derive(Serialize)]
+struct Description<'a> {
+	description: &'a str,
+	#[serde(rename = "type")]
+	kind: &'a str,
+	tags: &'a [String],
+}
+
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+	let (pattern, text) = (pattern.as_bytes(), text.as_bytes());
+	let (mut p, mut t) = (0, 0);
+	let mut backtrack = None;
+
+	while t < text.len() {
+		if p < pattern.len() && pattern[p] == b'*' {
+			backtrack = Some((p, t));
+			p += 1;
+		} else if p < pattern.len() && pattern[p].eq_ignore_ascii_case(&text[t]) {
+			p += 1;
+			t += 1;
+		} else if let Some((star, matched)) = backtrack {
+			p = star + 1;
+			t = matched + 1;
+			backtrack = Some((star, matched + 1));
+		} else {
+			return false;
+		}
+	}
+
+	pattern[p..].iter().all(|&c| c == b'*')
+}
+
+pub fn is_whitelisted(path: &str) -> bool {
+	WHITELIST.iter().any(|pattern| wildcard_match(pattern, path))
+}
+
+fn write_cstr(out: &mut impl Write, value: &str) -> io::Result<()> {
+	out.write_all(value.as_bytes())?;
+	out.write_all(&[0])
+}
+
+fn crc32_file(path: &Path) -> io::Result<(u64, u32)> {
+	let mut file = fs::File::open(path)?;
+	let mut hasher = crc32fast::Hasher::new();
+	let mut buf = [0u8; 64 * 1024];
+	let mut size = 0u64;
+	loop {
+		let read = file.read(&mut buf)?;
+		if read == 0 {
+			break;
+		}
+		hasher.update(&buf[..read]);
+		size += read as u64;
+	}
+	Ok((size, hasher.finalize()))
+}
+
+// Feeds everything written through it into the trailing archive CRC
+struct CrcWriter<W: Write> {
+	inner: W,
+	hasher: crc32fast::Hasher,
+}
+
+impl<W: Write> Write for CrcWriter<W> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let written = self.inner.write(buf)?;
+		self.hasher.update(&buf[..written]);
+		Ok(written)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.inner.flush()
+	}
+}
+
+// Packs the addon folder at `source` into a .gma at `out`, replacing it atomically.
+// Returns the number of packed files
+pub fn write(source: &Path, out: &Path, fallback_title: &str) -> Result<usize, String> {
+	let info: AddonJson = fs::read_to_string(source.join("addon.json"))
+		.ok()
+		.and_then(|content| serde_json::from_str(&content).ok())
+		.unwrap_or_default();
+	let title = if info.title.is_empty() { fallback_title } else { info.title.as_str() };
+
+	let mut entries = Vec::new();
+	for relative in manifest::list_files(source) {
+		if relative == "addon.json" || info.ignore.iter().any(|pattern| wildcard_match(pattern, &relative)) {
+			continue;
+		}
+		if !is_whitelisted(&relative) {
+			print_log(&format!("Not packing {}: file type not allowed in a .gma", relative));
+			continue;
+		}
+		let (size, crc) = crc32_file(&source.join(&relative)).map_err(|e| format!("Failed to read {}: {}", relative, e))?;
+		entries.push((relative, size, crc));
+	}
+
+	let description = serde_json::to_string(&Description { description: &info.description, kind: &info.kind, tags: &info.tags })
+		.map_err(|e| e.to_string())?;
+	let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+	let tmp_path = out.with_extension("gma.tmp");
+	let result = (|| -> io::Result<()> {
+		let file = fs::File::create(&tmp_path)?;
+		let mut writer = CrcWriter { inner: BufWriter::new(file), hasher: crc32fast::Hasher::new() };
+
+		writer.write_all(IDENT)?;
+		writer.write_all(&[VERSION])?;
+		writer.write_all(&0u64.to_le_bytes())?; // SteamID, unused
+		writer.write_all(&timestamp.to_le_bytes())?;
+		write_cstr(&mut writer, "")?; // Required content, unused
+		write_cstr(&mut writer, title)?;
+		write_cstr(&mut writer, &description)?;
+		write_cstr(&mut writer, "Author Name")?;
+		writer.write_all(&1i32.to_le_bytes())?; // Addon version, unused
+
+		for (index, (relative, size, crc)) in entries.iter().enumerate() {
+			writer.write_all(&(index as u32 + 1).to_le_bytes())?;
+			write_cstr(&mut writer, &relative.to_lowercase())?;
+			writer.write_all(&(*size as i64).to_le_bytes())?;
+			writer.write_all(&crc.to_le_bytes())?;
+		}
+		writer.write_all(&0u32.to_le_bytes())?;
+
+		for (relative, _, _) in &entries {
+			io::copy(&mut fs::File::open(source.join(relative))?, &mut writer)?;
+		}
+
+		let crc = writer.hasher.clone().finalize();
+		let mut inner = writer.inner;
+		inner.write_all(&crc.to_le_bytes())?;
+		inner.flush()?;
+		inner.get_ref().sync_all()
+	})();
+
+	if let Err(e) = result.and_then(|_| fs::rename(&tmp_path, out)) {
+		let _ = fs::remove_file(&tmp_path);
+		return Err(format!("Failed to write {}: {}", out.display(), e));
+	}
+
+	Ok(entries.len())
+}
+
+struct Reader<'a> {
+	data: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> Reader<'a> {
+	fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+		let end = self.pos.checked_add(len).filter(|&end| end <= self.data.len()).ok_or("Unexpected end of .gma")?;
+		let bytes = &self.data[self.pos..end];
+		self.pos = end;
+		Ok(bytes)
+	}
+
+	fn u32(&mut self) -> Result<u32, String> {
+		Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap_or_default()))
+	}
+
+	fn u64(&mut self) -> Result<u64, String> {
+		Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap_or_default()))
+	}
+
+	fn cstr(&mut self) -> Result<String, String> {
+		let len = self.data[self.pos..].iter().position(|&c| c == 0).ok_or("Unterminated string in .gma")?;
+		let value = String::from_utf8_lossy(self.take(len)?).into_owned();
+		self.pos += 1;
+		Ok(value)
+	}
+}
+
+// Same rule as zip extraction: relative paths that stay inside the destination
+pub fn safe_entry_path(name: &str) -> Option<PathBuf> {
+	let path = Path::new(name);
+	let safe = !name.is_empty()
+		&& !name.contains('\\')
+		&& path.components().all(|c| matches!(c, std::path::Component::Normal(_)));
+	safe.then(|| path.to_path_buf())
+}
+
+struct Entry {
+	// As stored, `/`-separated
+	name: String,
+	relative: PathBuf,
+	size: usize,
+	crc: u32,
+}
+
+// Reads the header up to the file contents, returning the title and the entries
+fn read_header(reader: &mut Reader) -> Result<(String, Vec<Entry>), String> {
+	if reader.take(4)? != IDENT {
+		return Err("Not a .gma file".to_string());
+	}
+	let version = reader.take(1)?[0];
+	if version > VERSION {
+		return Err(format!("Unsupported .gma version {}", version));
+	}
+
+	reader.u64()?; // SteamID
+	reader.u64()?; // Timestamp
+	if version > 1 {
+		// Required content, a list terminated by an empty string
+		while !reader.cstr()?.is_empty() {}
+	}
+	let title = reader.cstr()?;
+	reader.cstr()?; // Description
+	reader.cstr()?; // Author
+	reader.u32()?; // Addon version
+
+	let mut entries = Vec::new();
+	while reader.u32()? != 0 {
+		let name = reader.cstr()?;
+		let size = reader.u64()?;
+		let crc = reader.u32()?;
+		let relative = safe_entry_path(&name).ok_or_else(|| format!("Suspicious file path in .gma: {}", name))?;
+		entries.push(Entry { name, relative, size: size as usize, crc });
+	}
+	Ok((title, entries))
+}
+
+// Paths of the files in the .gma at `path`, without reading their contents
+pub fn list(path: &Path) -> Result<Vec<PathBuf>, String> {
+	let data = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+	let (_, entries) = read_header(&mut Reader { data: &data, pos: 0 })?;
+	Ok(entries.into_iter().map(|entry| entry.relative).collect())
+}
+
+// Unpacks the files of the .gma at `path` that `keep` accepts into `dest`, after checking its
+// structure and CRCs. Returns the number of extracted files
+pub fn extract(path: &Path, dest: &Path, keep: &mut impl FnMut(&str) -> bool) -> Result<usize, String> {
+	let data = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+	let mut reader = Reader { data: &data, pos: 0 };
+	let (title, entries) = read_header(&mut reader)?;
+
+	let mut contents = Vec::with_capacity(entries.len());
+	for entry in &entries {
+		let content = reader.take(entry.size)?;
+		if crc32fast::hash(content) != entry.crc {
+			return Err(format!("CRC mismatch for {} in .gma", entry.relative.display()));
+		}
+		contents.push(content);
+	}
+
+	// gmad appends a CRC of the whole archive, older tools write 0
+	let body_len = reader.pos;
+	if let Ok(crc) = reader.u32()
+		&& crc != 0
+		&& crc != crc32fast::hash(&data[..body_len])
+	{
+		return Err("Archive CRC mismatch in .gma".to_string());
+	}
+
+	fs::create_dir_all(dest).map_err(|e| format!("Failed to create staging directory: {:?}", e))?;
+	let mut extracted = 0;
+	for (entry, content) in entries.iter().zip(contents) {
+		if !keep(&entry.name) {
+			continue;
+		}
+
+		let out_path = dest.join(&entry.relative);
+		if let Some(parent) = out_path.parent() {
+			fs::create_dir_all(parent).map_err(|e| format!("Failed to create directory: {}", e))?;
+		}
+		fs::write(&out_path, content).map_err(|e| format!("Failed to extract file {}: {}", out_path.display(), e))?;
+		extracted += 1;
+	}
+
+	print_log(&format!("Extracted {} file(s) from \"{}\"", extracted, title));
+	Ok(extracted)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// A fresh directory below the system temp dir, unique per test
+	fn scratch(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("gma_test_{}_{}", std::process::id(), name));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	fn packed(dir: &Path) -> PathBuf {
+		let source = dir.join("source");
+		fs::create_dir_all(source.join("lua/autorun")).unwrap();
+		fs::create_dir_all(source.join("materials")).unwrap();
+		fs::write(source.join("addon.json"), r#"{"title": "Test", "ignore": ["*.psd"]}"#).unwrap();
+		fs::write(source.join("lua/autorun/init.lua"), "print('hello')").unwrap();
+		fs::write(source.join("materials/icon.png"), [0u8, 1, 2, 3]).unwrap();
+		fs::write(source.join("materials/icon.psd"), "ignored").unwrap();
+		fs::write(source.join("README.md"), "not whitelisted").unwrap();
+
+		let out = dir.join("addon.gma");
+		assert_eq!(write(&source, &out, "fallback").unwrap(), 2);
+		out
+	}
+
+	#[test]
+	fn round_trips_through_write_and_extract() {
+		let dir = scratch("round_trip");
+		let gma = packed(&dir);
+
+		assert_eq!(list(&gma).unwrap(), vec![PathBuf::from("lua/autorun/init.lua"), PathBuf::from("materials/icon.png")]);
+
+		let dest = dir.join("dest");
+		assert_eq!(extract(&gma, &dest, &mut |_| true).unwrap(), 2);
+		assert_eq!(fs::read_to_string(dest.join("lua/autorun/init.lua")).unwrap(), "print('hello')");
+		assert_eq!(fs::read(dest.join("materials/icon.png")).unwrap(), [0u8, 1, 2, 3]);
+		assert!(!dest.join("README.md").exists());
+		assert!(!dest.join("materials/icon.psd").exists());
+
+		let _ = fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn rejects_malformed_archives() {
+		let dir = scratch("malformed");
+		let gma = packed(&dir);
+		let data = fs::read(&gma).unwrap();
+		let broken = dir.join("broken.gma");
+		let dest = dir.join("dest");
+
+		let mut bad_ident = data.clone();
+		bad_ident[0] = b'X';
+		fs::write(&broken, &bad_ident).unwrap();
+		assert_eq!(list(&broken).unwrap_err(), "Not a .gma file");
+
+		let mut bad_version = data.clone();
+		bad_version[4] = VERSION + 1;
+		fs::write(&broken, &bad_version).unwrap();
+		assert!(list(&broken).is_err());
+
+		fs::write(&broken, &data[..data.len() / 2]).unwrap();
+		assert!(extract(&broken, &dest, &mut |_| true).is_err());
+
+		let content = data.windows(5).position(|window| window == b"print").unwrap();
+		let mut bad_file_crc = data.clone();
+		bad_file_crc[content] = b'P';
+		fs::write(&broken, &bad_file_crc).unwrap();
+		assert!(extract(&broken, &dest, &mut |_| true).unwrap_err().starts_with("CRC mismatch"));
+
+		let mut bad_archive_crc = data.clone();
+		let last = bad_archive_crc.len() - 1;
+		bad_archive_crc[last] ^= 0xff;
+		fs::write(&broken, &bad_archive_crc).unwrap();
+		assert_eq!(extract(&broken, &dest, &mut |_| true).unwrap_err(), "Archive CRC mismatch in .gma");
+		assert!(!dest.exists());
+
+		let _ = fs::remove_dir_all(&dir);
+	}
+}
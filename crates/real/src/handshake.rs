@@ -0,0 +1,83 @@
+use std::{
+	ffi::{CStr, c_char},
+	sync::OnceLock,
+};
+
+use crate::{BIN_DIR, VERSION_FILE, print_log};
+
+// Loader ABI this module needs, returned from gmod_integration_init
+const REQUIRED_LOADER_ABI: u32 = 1;
+// Newest LoaderContext layout this module knows
+const KNOWN_CONTEXT_ABI: u32 = 1;
+
+// Mirrors LoaderContext in crates/loader/src/abi.rs, fields are only ever appended
+#[repr(C)]
+pub struct LoaderContext {
+	struct_version: u32,
+	struct_size: u32,
+	abi_version: u32,
+	min_compatible_abi: u32,
+	loader_version: *const c_char,
+	config_json: *const c_char,
+	log: extern "C" fn(*const c_char),
+	data_dir: *const c_char,
+	bin_dir: *const c_char,
+	version_file: *const c_char,
+}
+
+// What the loader told us, copied out of the context
+pub struct LoaderInfo {
+	pub version: String,
+	pub abi: u32,
+	// updater.json as the loader resolved it
+	pub config_json: Option<String>,
+	pub bin_dir: Option<String>,
+	pub version_file: Option<String>,
+}
+
+static LOADER: OnceLock<LoaderInfo> = OnceLock::new();
+
+pub fn loader() -> Option<&'static LoaderInfo> {
+	LOADER.get()
+}
+
+unsafe fn read_str(ptr: *const c_char) -> Option<String> {
+	if ptr.is_null() {
+		return None;
+	}
+	Some(unsafe { CStr::from_ptr(ptr) }.to_string_lossy().into_owned())
+}
+
+// Called by loaders with ABI 1 or newer before gmod13_open
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn gmod_integration_init(context: *const LoaderContext) -> u32 {
+	let Some(context) = (unsafe { context.as_ref() }) else {
+		return REQUIRED_LOADER_ABI;
+	};
+	if (context.struct_size as usize) < size_of::<LoaderContext>() || context.min_compatible_abi > KNOWN_CONTEXT_ABI {
+		print_log(&format!("Loader context ABI {} is not understood by this module, ignoring it", context.abi_version));
+		return REQUIRED_LOADER_ABI;
+	}
+
+	let info = unsafe {
+		LoaderInfo {
+			version: read_str(context.loader_version).unwrap_or_default(),
+			abi: context.abi_version,
+			config_json: read_str(context.config_json),
+			bin_dir: read_str(context.bin_dir),
+			version_file: read_str(context.version_file),
+		}
+	};
+
+	if let Ok(msg) = std::ffi::CString::new(format!("Real integration {} accepted the loader context", env!("CARGO_PKG_VERSION"))) {
+		(context.log)(msg.as_ptr());
+	}
+	for (name, theirs, ours) in [("bin", &info.bin_dir, BIN_DIR), ("version file", &info.version_file, VERSION_FILE)] {
+		if let Some(theirs) = theirs.as_deref().filter(|theirs| *theirs != ours) {
+			print_log(&format!("Warning: the loader uses {} as {}, this module uses {}", theirs, name, ours));
+		}
+	}
+
+	let _ = LOADER.set(info);
+	REQUIRED_LOADER_ABI
+}
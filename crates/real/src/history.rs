@@ -0,0 +1,76 @@
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+use crate::{print_log, rewrite::Rewrite};
+
+const HISTORY_FILE: &str = "garrysmod/data/gm_integration/update_history.json";
+const HISTORY_LIMIT: usize = 50;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateStatus {
+	Installed,
+	Rejected,
+	Failed,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct HistoryEntry {
+	pub time: String,
+	pub version: String,
+	pub previous_version: Option<String>,
+	pub status: UpdateStatus,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub errors: Vec<String>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub rewrites: Vec<Rewrite>,
+	// Release files left out by the content filters
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub skipped: Vec<String>,
+	// User-owned files whose local edits were kept
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub kept_local: Vec<String>,
+}
+
+impl HistoryEntry {
+	pub fn new(version: &str, previous_version: Option<&String>, status: UpdateStatus) -> Self {
+		HistoryEntry {
+			time: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+			version: version.to_string(),
+			previous_version: previous_version.cloned(),
+			status,
+			errors: Vec::new(),
+			rewrites: Vec::new(),
+			skipped: Vec::new(),
+			kept_local: Vec::new(),
+		}
+	}
+}
+
+fn load_history() -> Vec<HistoryEntry> {
+	fs::read_to_string(HISTORY_FILE)
+		.ok()
+		.and_then(|content| serde_json::from_str(&content).ok())
+		.unwrap_or_default()
+}
+
+// Appends an entry to the update history, keeping only the most recent ones
+pub fn record(entry: HistoryEntry) {
+	let mut history = load_history();
+	history.push(entry);
+	if history.len() > HISTORY_LIMIT {
+		history.drain(..history.len() - HISTORY_LIMIT);
+	}
+
+	if let Some(parent) = Path::new(HISTORY_FILE).parent() {
+		let _ = fs::create_dir_all(parent);
+	}
+
+	let result = serde_json::to_string_pretty(&history)
+		.map_err(|e| e.to_string())
+		.and_then(|content| fs::write(HISTORY_FILE, content).map_err(|e| e.to_string()));
+	if let Err(e) = result {
+		print_log(&format!("Failed to save update history: {}", e));
+	}
+}
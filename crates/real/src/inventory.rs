@@ -0,0 +1,189 @@
+use chrono::Local;
+use regex::Regex;
+use reqwest::blocking::Client;
+use serde::Serialize;
+use std::{collections::BTreeMap, fs, path::Path};
+
+use crate::{
+	BIN_DIR, Release,
+	config::{Config, Dependency},
+	delta,
+	dependencies::{self, ReleaseCache},
+	get_platform_suffix, manifest, print_log,
+};
+
+// Read by Lua with file.Read("gm_integration/bin_inventory.json", "DATA")
+const REPORT_FILE: &str = "garrysmod/data/gm_integration/bin_inventory.json";
+
+#[derive(Serialize)]
+struct Module {
+	file: String,
+	name: String,
+	// `server` for gmsv_, `client` for gmcl_
+	realm: &'static str,
+	platform: String,
+	size: u64,
+	sha256: String,
+	// Id of the dependency that provides the module
+	dependency: Option<String>,
+	installed: Option<String>,
+	// Newest release upstream, and the newest the version constraint allows
+	latest: Option<String>,
+	allowed: Option<String>,
+	outdated: bool,
+	platform_mismatch: bool,
+	// Not provided by any configured dependency
+	orphan: bool,
+}
+
+#[derive(Serialize)]
+struct Report {
+	time: String,
+	platform: &'static str,
+	modules: Vec<Module>,
+}
+
+// Upstream state of a dependency, fetched once per inventory
+struct Feed {
+	releases: Vec<Release>,
+	latest: Option<String>,
+	allowed: Option<String>,
+}
+
+fn fetch(client: &Client, dependency: &Dependency, cache: &mut ReleaseCache) -> Option<Feed> {
+	let releases = match cache.get(client, dependency) {
+		Ok(releases) => releases.to_vec(),
+		Err(e) => {
+			print_log(&format!("Inventory: failed to fetch releases of {}: {}", dependency.id, e));
+			return None;
+		}
+	};
+
+	let sorted = dependencies::sorted_releases(&releases);
+	let latest = sorted.first().map(|(_, release)| release.tag_name.clone());
+	let allowed = match dependencies::requirement(dependency) {
+		Some(requirement) => sorted.iter().find(|(version, _)| requirement.matches(version)).map(|(_, r)| r.tag_name.clone()),
+		None => latest.clone(),
+	};
+
+	Some(Feed { releases, latest, allowed })
+}
+
+// Release whose assets include one with this hash
+fn find_by_hash<'a>(feed: &'a Feed, sha256: &str) -> Option<&'a Release> {
+	feed.releases
+		.iter()
+		.find(|release| release.assets.iter().any(|asset| delta::expected_sha256(asset) == Some(sha256)))
+}
+
+// Scans lua/bin, compares each module with its upstream releases and writes the report. Release
+// lists the dependency update already fetched are taken from `cache`
+pub fn run(client: &Client, config: &Config, versions: &BTreeMap<String, String>, cache: &mut ReleaseCache) {
+	let Ok(module_name) = Regex::new(r"^(gmsv|gmcl)_(.+)_(win32|win64|linux|linux64|osx|osx64)\.dll$") else {
+		return;
+	};
+	let Ok(entries) = fs::read_dir(BIN_DIR) else {
+		return;
+	};
+
+	let enabled: Vec<&Dependency> = config.dependencies.0.iter().filter(|dependency| dependency.enabled).collect();
+	let mut feeds: BTreeMap<String, Option<Feed>> = BTreeMap::new();
+	let platform = get_platform_suffix();
+	let mut modules = Vec::new();
+
+	let mut files: Vec<String> = entries.flatten().map(|entry| entry.file_name().to_string_lossy().into_owned()).collect();
+	files.sort();
+
+	for file in files {
+		let Some(captures) = module_name.captures(&file) else {
+			continue;
+		};
+		let name = captures[2].to_string();
+		let suffix = captures[3].to_string();
+		let Ok(record) = manifest::hash_file(&Path::new(BIN_DIR).join(&file)) else {
+			continue;
+		};
+
+		let mut module = Module {
+			realm: if &captures[1] == "gmsv" { "server" } else { "client" },
+			platform_mismatch: suffix != platform,
+			file: file.clone(),
+			name: name.clone(),
+			platform: suffix.clone(),
+			size: record.size,
+			sha256: record.sha256,
+			dependency: None,
+			installed: None,
+			latest: None,
+			allowed: None,
+			outdated: false,
+			orphan: false,
+		};
+
+		// The loader and this module are updated by the loader itself
+		if name.starts_with("gmod_integration") {
+			module.dependency = Some("gmod_integration".to_string());
+			modules.push(module);
+			continue;
+		}
+
+		// By name first, then by hash for renamed copies
+		let mut dependency = enabled.iter().find(|dependency| dependencies::installs_as(dependency, &file, &suffix));
+		if dependency.is_none() {
+			dependency = enabled.iter().find(|dependency| {
+				let feed = feeds.entry(dependency.id.clone()).or_insert_with(|| fetch(client, dependency, cache));
+				feed.as_ref().is_some_and(|feed| find_by_hash(feed, &module.sha256).is_some())
+			});
+		}
+
+		let Some(dependency) = dependency else {
+			module.orphan = true;
+			modules.push(module);
+			continue;
+		};
+
+		let feed = feeds.entry(dependency.id.clone()).or_insert_with(|| fetch(client, dependency, cache));
+		module.dependency = Some(dependency.id.clone());
+		module.installed = feed
+			.as_ref()
+			.and_then(|feed| find_by_hash(feed, &module.sha256))
+			.map(|release| release.tag_name.clone())
+			.or_else(|| versions.get(&dependency.id).filter(|_| !module.platform_mismatch).cloned());
+		if let Some(feed) = feed {
+			module.latest = feed.latest.clone();
+			module.allowed = feed.allowed.clone();
+			module.outdated = module.installed.is_some() && module.installed != feed.allowed && feed.allowed.is_some();
+		}
+
+		modules.push(module);
+	}
+
+	for module in &modules {
+		if module.platform_mismatch {
+			print_log(&format!("Inventory: {} is built for {}, this server needs {}", module.file, module.platform, platform));
+		}
+		if module.orphan {
+			print_log(&format!("Inventory: {} is not provided by any configured dependency", module.file));
+		}
+		if module.outdated {
+			print_log(&format!(
+				"Inventory: {} is at {}, {} is available",
+				module.file,
+				module.installed.as_deref().unwrap_or("unknown"),
+				module.allowed.as_deref().unwrap_or("unknown")
+			));
+		}
+	}
+	print_log(&format!("Inventory: {} module(s) in {}, report written to {}", modules.len(), BIN_DIR, REPORT_FILE));
+
+	let report = Report { time: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), platform, modules };
+	if let Some(parent) = Path::new(REPORT_FILE).parent() {
+		let _ = fs::create_dir_all(parent);
+	}
+	let result = serde_json::to_string_pretty(&report)
+		.map_err(|e| e.to_string())
+		.and_then(|content| fs::write(REPORT_FILE, content).map_err(|e| e.to_string()));
+	if let Err(e) = result {
+		print_log(&format!("Failed to save inventory report: {}", e));
+	}
+}
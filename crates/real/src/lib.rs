@@ -1,47 +1,85 @@
+mod config;
+mod conflicts;
+mod dependencies;
+mod filter;
+mod gc;
+mod git_source;
+mod gma;
+mod handshake;
+mod history;
+mod inventory;
+mod lua_syntax;
+mod manifest;
+mod rename;
+mod rewrite;
+mod user_files;
+
 use gmod::{gmod13_close, gmod13_open, lua::State};
+use gmod_integration_delta::{self as delta, Asset};
 use serde::{Deserialize, Serialize};
-use std::{fs, io::copy, path::{Path, PathBuf}};
+use std::{collections::BTreeMap, fs, io::copy, path::{Path, PathBuf}};
 use reqwest::blocking::Client;
 use zip::ZipArchive;
 use chrono::Local;
 use std::time::Duration;
+use config::{Config, GmaInstall, ReleaseManifest, VerifyMode};
+use history::{HistoryEntry, UpdateStatus};
+use manifest::{Drift, InstallManifest};
+use rewrite::Rewrite;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 struct Release {
 	tag_name: String,
 	assets: Vec<Asset>,
-}
-
-#[derive(Deserialize, Debug)]
-struct Asset {
-	name: String,
-	browser_download_url: String,
+	#[serde(default)]
+	draft: bool,
+	#[serde(default)]
+	prerelease: bool,
 }
 
 #[derive(Deserialize, Serialize, Default)]
 struct VersionCache {
 	gmod_integration_loader: Option<String>,
+	// File in lua/bin the loader runs this module from
+	#[serde(default)]
+	gmod_integration_active: Option<String>,
 	gmod_integration: Option<String>,
-	gwsockets: Option<String>,
-	reqwest: Option<String>,
+	// Installed release tag of each dependency, keyed by its id
+	#[serde(default)]
+	dependencies: BTreeMap<String, String>,
+	// Keys written by other versions of the loader or updater
+	#[serde(flatten)]
+	extra: serde_json::Map<String, serde_json::Value>,
 }
 
 const VERSION_FILE: &str = "garrysmod/lua/bin/versions.json";
 const BIN_DIR: &str = "garrysmod/lua/bin";
-const GWSOCKETS_API: &str = "https://api.github.com/repos/FredyH/GWSockets/releases/latest";
-const REQWEST_API: &str = "https://api.github.com/repos/WilliamVenner/gmsv_reqwest/releases/latest";
 const TMP_JSON_PATH: &str = "garrysmod/data/gm_integration/tmp.json";
+const ZIP_DOWNLOAD_PATH: &str = "gmod-integration.zip";
+const GMA_DOWNLOAD_PATH: &str = "gmod-integration.gma";
+const ADDONS_DIR: &str = "./garrysmod/addons";
+const STAGING_DIR: &str = "garrysmod/data/gm_integration/staging";
+const BACKUP_DIR: &str = "garrysmod/data/gm_integration/previous";
 
-fn print_log(msg: &str) {
+pub(crate) fn print_log(msg: &str) {
 	let time = Local::now().format("%Y-%m-%d %H:%M:%S");
 	println!(" | {} | Gmod Integration | Auto Updater: {}", time, msg);
 }
 
 fn load_version_cache() -> VersionCache {
-	fs::read_to_string(VERSION_FILE)
+	let mut cache: VersionCache = fs::read_to_string(VERSION_FILE)
 		.ok()
 		.and_then(|content| serde_json::from_str(&content).ok())
-		.unwrap_or_default()
+		.unwrap_or_default();
+
+	// Versions written before dependencies were configurable
+	for legacy in ["gwsockets", "reqwest"] {
+		if let Some(serde_json::Value::String(version)) = cache.extra.remove(legacy) {
+			cache.dependencies.entry(legacy.to_string()).or_insert(version);
+		}
+	}
+
+	cache
 }
 
 fn save_version_cache(cache: &VersionCache) {
@@ -58,87 +96,6 @@ fn get_platform_suffix() -> &'static str {
 	}
 }
 
-fn download_dependency_asset(client: &Client, asset: &Asset) -> Result<(), Box<dyn std::error::Error>> {
-	// Download dependency binary from GitHub releases
-	let mut resp = client
-		.get(&asset.browser_download_url)
-		.header("User-Agent", "Gmod-Integration-Updater")
-		.timeout(Duration::from_secs(30))
-		.send()?;
-
-	// Check if response is successful
-	if !resp.status().is_success() {
-		return Err(format!("HTTP error: {}", resp.status()).into());
-	}
-
-	let mut out_path = PathBuf::from(BIN_DIR);
-	out_path.push(&asset.name);
-
-	// Ensure bin directory exists
-	fs::create_dir_all(BIN_DIR)?;
-
-	// Download to temporary file then rename for atomic operation
-	let tmp_path = out_path.with_extension("tmp");
-	let mut file = fs::File::create(&tmp_path)?;
-	copy(&mut resp, &mut file)?;
-	
-	// Verify file was written and has content
-	let metadata = fs::metadata(&tmp_path)?;
-	if metadata.len() == 0 {
-		fs::remove_file(&tmp_path)?;
-		return Err("Downloaded file is empty".into());
-	}
-
-	fs::rename(tmp_path, &out_path)?;
-	
-	print_log(&format!("Downloaded {}", asset.name));
-	Ok(())
-}
-
-fn download_dependency(client: &Client, api_url: &str, dep_name: &str, current_version: Option<&String>) -> Result<Option<String>, Box<dyn std::error::Error>> {
-	// Fetch latest release information for dependency
-	let release: Release = client
-		.get(api_url)
-		.header("User-Agent", "Gmod-Integration-Updater")
-		.timeout(Duration::from_secs(30))
-		.send()?
-		.error_for_status()?
-		.json()?;
-
-	// Validate release data
-	if release.tag_name.is_empty() {
-		return Err(format!("Invalid release for {}: empty tag name", dep_name).into());
-	}
-
-	// Skip if already up to date
-	if let Some(current) = current_version {
-		if current == &release.tag_name {
-			print_log(&format!("{} is up to date ({})", dep_name, release.tag_name));
-			return Ok(None);
-		}
-	}
-
-	// Find and download the correct binary for current platform
-	let suffix = get_platform_suffix();
-	let target_name = format!("gmsv_{}_{}.dll", dep_name.to_lowercase(), suffix);
-	
-	for asset in &release.assets {
-		if asset.name == target_name {
-			if let Err(e) = download_dependency_asset(client, asset) {
-				print_log(&format!("Failed to download {}: {}", asset.name, e));
-				// Clean up any partial download
-				let partial_path = PathBuf::from(BIN_DIR).join(&asset.name);
-				let _ = fs::remove_file(partial_path);
-				return Err(e);
-			}
-			return Ok(Some(release.tag_name));
-		}
-	}
-	
-	print_log(&format!("No matching asset found for {} on {}", dep_name, suffix));
-	Ok(None)
-}
-
 fn update_tmp_json() {
 	// Create directory if it doesn't exist
 	if let Some(parent) = Path::new(TMP_JSON_PATH).parent() {
@@ -157,123 +114,153 @@ fn update_tmp_json() {
 	}
 }
 
-#[gmod13_open]
-fn gmod13_open(_lua: State) -> i32 {
-	print_log("Starting auto-updater...");
+// Finds the addon root inside an archive from its entry names: the archive itself when it
+// holds the addon at its top level, otherwise its single top-level folder. Returns the prefix
+// to strip from entry names
+fn archive_root<'a>(names: impl Iterator<Item = &'a str>) -> Result<String, String> {
+	let mut top_level = BTreeMap::new();
+	for name in names {
+		if name == "addon.json" || name.starts_with("lua/") {
+			return Ok(String::new());
+		}
+		match name.split_once('/') {
+			Some((folder, _)) => top_level.insert(folder, true),
+			None => top_level.insert(name, false),
+		};
+	}
 
-	let mut version_cache = load_version_cache();
-	let client = Client::builder()
-		.timeout(Duration::from_secs(30))
-		.build()
-		.unwrap_or_else(|_| Client::new());
+	let entries: Vec<_> = top_level.into_iter().collect();
+	match entries.as_slice() {
+		[] => Err("Archive is empty".to_string()),
+		[(root, true)] => Ok(format!("{}/", root)),
+		_ => Err(format!("Archive has no lua folder and {} top-level entries instead of a single root folder", entries.len())),
+	}
+}
 
-	// Update dependencies first (GWsockets and reqwest)
-	print_log("Checking dependencies...");
+// Extracts the files that `keep` accepts, with paths relative to the addon root
+fn extract_archive(zip_path: &Path, dest: &Path, keep: &mut impl FnMut(&str) -> bool) -> Result<(), String> {
+	let file = fs::File::open(zip_path).map_err(|e| format!("Failed to open zip file: {:?}", e))?;
+	let mut archive = ZipArchive::new(file).map_err(|e| format!("Failed to read zip archive: {:?}", e))?;
+	let root = archive_root(archive.file_names())?;
 
-	// Download GWsockets with error recovery
-	match download_dependency(&client, GWSOCKETS_API, "gwsockets", version_cache.gwsockets.as_ref()) {
-		Ok(Some(new_version)) => {
-			version_cache.gwsockets = Some(new_version);
-			print_log("GWsockets updated");
-		}
-		Ok(None) => {}, // Up to date
-		Err(e) => {
-			print_log(&format!("Failed to update GWsockets: {}", e));
-			// Continue with other dependencies
+	fs::create_dir_all(dest).map_err(|e| format!("Failed to create staging directory: {:?}", e))?;
+
+	for i in 0..archive.len() {
+		let mut file = archive.by_index(i).map_err(|e| format!("Failed to read zip entry {}: {}", i, e))?;
+
+		// Prevent directory traversal attacks
+		let Some(name) = file.enclosed_name() else {
+			return Err(format!("Suspicious file path in zip: {}", file.name()));
+		};
+		let relative = file.name().strip_prefix(root.as_str()).unwrap_or_default().to_string();
+		if file.is_dir() || relative.is_empty() || !keep(&relative) {
+			continue;
 		}
-	}
+		let out_path = dest.join(name.strip_prefix(&root).unwrap_or(name));
 
-	// Download reqwest with error recovery
-	match download_dependency(&client, REQWEST_API, "reqwest", version_cache.reqwest.as_ref()) {
-		Ok(Some(new_version)) => {
-			version_cache.reqwest = Some(new_version);
-			print_log("reqwest updated");
+		if let Some(parent) = out_path.parent()
+			&& let Err(e) = fs::create_dir_all(parent)
+		{
+			print_log(&format!("Failed to create directory: {}", e));
+			continue;
 		}
-		Ok(None) => {}, // Up to date
-		Err(e) => {
-			print_log(&format!("Failed to update reqwest: {}", e));
-			// Continue with main integration
+
+		match fs::File::create(&out_path) {
+			Ok(mut out_file) => {
+				if let Err(e) = copy(&mut file, &mut out_file) {
+					print_log(&format!("Failed to extract file {}: {}", out_path.display(), e));
+				}
+			}
+			Err(e) => {
+				print_log(&format!("Failed to create file {}: {}", out_path.display(), e));
+			}
 		}
 	}
 
-	// Save dependency versions (even if some failed)
-	save_version_cache(&version_cache);
+	Ok(())
+}
 
-	// Now update the main gmod integration addon
-	print_log("Checking Gmod Integration...");
+fn fetch_release_manifest(client: &Client, release: &Release, asset_name: &str) -> Option<ReleaseManifest> {
+	let asset = release.assets.iter().find(|asset| asset.name == asset_name)?;
 
-	// Fetch latest gmod integration release with timeout
-	let res = match client
-		.get("https://api.github.com/repos/gmod-integration/gmod-integration/releases/latest")
+	let manifest = client
+		.get(&asset.browser_download_url)
 		.header("User-Agent", "Gmod-Integration-Updater")
 		.timeout(Duration::from_secs(30))
 		.send()
-	{
-		Ok(r) => r,
+		.and_then(|r| r.error_for_status())
+		.and_then(|r| r.json());
+
+	match manifest {
+		Ok(manifest) => Some(manifest),
 		Err(e) => {
-			print_log(&format!("Failed to fetch release info: {:?}", e));
-			return 1;
+			print_log(&format!("Failed to fetch release manifest: {}", e));
+			None
 		}
-	};
+	}
+}
 
-	// Check response status
-	if !res.status().is_success() {
-		print_log(&format!("API request failed with status: {}", res.status()));
-		return 1;
+fn move_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+	if fs::rename(from, to).is_ok() {
+		return Ok(());
 	}
 
-	let release: Release = match res.json() {
-		Ok(r) => r,
-		Err(e) => {
-			print_log(&format!("Failed to parse release data: {:?}", e));
-			return 1;
-		}
-	};
+	// Fall back to copying when the rename crosses filesystems
+	copy_dir(from, to)?;
+	fs::remove_dir_all(from)
+}
 
-	// Validate release data
-	if release.tag_name.is_empty() {
-		print_log("Invalid release: empty tag name");
-		return 1;
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+	fs::create_dir_all(to)?;
+	for entry in fs::read_dir(from)? {
+		let entry = entry?;
+		let target = to.join(entry.file_name());
+		if entry.file_type()?.is_dir() {
+			copy_dir(&entry.path(), &target)?;
+		} else {
+			fs::copy(entry.path(), &target)?;
+		}
 	}
+	Ok(())
+}
 
-	// Check if addon folder exists and version matches
-	let addon_exists = Path::new("./garrysmod/addons/_gmod_integration_latest").exists();
-	
-	if let Some(current) = &version_cache.gmod_integration {
-		if current == &release.tag_name && addon_exists {
-			print_log(&format!("Gmod integration is up to date ({})", release.tag_name));
-			return 0;
+fn activate_staged(staging: &Path, target: &Path) -> Result<(), String> {
+	// Keep the current install aside until the new one is in place
+	let backup = Path::new(BACKUP_DIR);
+	let _ = fs::remove_dir_all(backup);
+
+	let had_previous = target.exists();
+	if had_previous {
+		if let Some(parent) = backup.parent() {
+			let _ = fs::create_dir_all(parent);
 		}
+		move_dir(target, backup).map_err(|e| format!("Failed to move current install aside: {}", e))?;
 	}
 
-	if !addon_exists {
-		print_log("Addon folder missing, downloading...");
-	} else {
-		print_log("Version mismatch, updating...");
+	if let Err(e) = move_dir(staging, target) {
+		let _ = fs::remove_dir_all(target);
+		if had_previous && let Err(restore_err) = move_dir(backup, target) {
+			print_log(&format!("Failed to restore previous install: {}", restore_err));
+		}
+		return Err(format!("Failed to move staged install into place: {}", e));
 	}
 
-	print_log("Downloading latest version...");
-
-	// Download source code archive from GitHub with timeout
-	let download_url = format!("https://github.com/gmod-integration/gmod-integration/archive/refs/tags/{}.zip", release.tag_name);
+	let _ = fs::remove_dir_all(backup);
+	Ok(())
+}
 
-	let response = match client
-		.get(&download_url)
+// Downloads an archive and checks that it starts with the expected magic bytes
+fn download_archive(client: &Client, url: &str, out_path: &Path, magic: &[u8]) -> Result<(), String> {
+	let response = client
+		.get(url)
 		.header("User-Agent", "Gmod-Integration-Updater")
 		.timeout(Duration::from_secs(120)) // Longer timeout for large files
 		.send()
-	{
-		Ok(r) => r,
-		Err(e) => {
-			print_log(&format!("Failed to download release: {:?}", e));
-			return 1;
-		}
-	};
+		.map_err(|e| format!("Failed to download release: {:?}", e))?;
 
 	// Check if response is successful
 	if !response.status().is_success() {
-		print_log(&format!("Download failed with status: {}", response.status()));
-		return 1;
+		return Err(format!("Download failed with status: {}", response.status()));
 	}
 
 	// Check content type
@@ -281,185 +268,441 @@ fn gmod13_open(_lua: State) -> i32 {
 		print_log(&format!("Content-Type: {:?}", content_type));
 	}
 
-	let bytes = match response.bytes() {
-		Ok(b) => b,
-		Err(e) => {
-			print_log(&format!("Failed to read download data: {:?}", e));
-			return 1;
-		}
-	};
+	let bytes = response.bytes().map_err(|e| format!("Failed to read download data: {:?}", e))?;
 
 	// Verify downloaded content size is reasonable
 	if bytes.len() > 100_000_000 { // 100MB limit
-		print_log("Downloaded file is suspiciously large");
-		return 1;
+		return Err("Downloaded file is suspiciously large".to_string());
 	}
 
 	if bytes.len() < 1000 { // Minimum reasonable size
-		print_log("Downloaded file is too small to be valid");
-		return 1;
+		return Err("Downloaded file is too small to be valid".to_string());
 	}
 
-	// Validate downloaded ZIP file
-	if bytes.len() < 4 || &bytes[0..4] != b"PK\x03\x04" {
-		print_log("Downloaded file is not a valid ZIP file");
-		return 1;
+	// Validate downloaded archive
+	if !bytes.starts_with(magic) {
+		return Err("Downloaded file is not a valid archive".to_string());
 	}
 
-	let zip_path = Path::new("gmod-integration.zip");
+	fs::write(out_path, &bytes).map_err(|e| format!("Failed to save archive: {:?}", e))
+}
 
-	if let Err(e) = fs::write(&zip_path, &bytes) {
-		print_log(&format!("Failed to save zip file: {:?}", e));
-		return 1;
-	}
+// Downloads a zip archive and extracts its addon root into `staging_dir`
+fn download_zip(client: &Client, url: &str, staging_dir: &Path, keep: &mut impl FnMut(&str) -> bool) -> Result<(), String> {
+	let zip_path = Path::new(ZIP_DOWNLOAD_PATH);
+	download_archive(client, url, zip_path, b"PK\x03\x04")?;
 
 	print_log("Extracting files...");
 
-	let file = match fs::File::open(&zip_path) {
-		Ok(f) => f,
-		Err(e) => {
-			print_log(&format!("Failed to open zip file: {:?}", e));
-			return 1;
+	let extracted = extract_archive(zip_path, staging_dir, keep);
+	let _ = fs::remove_file(zip_path);
+	extracted.map_err(|e| format!("Error: {}", e))
+}
+
+enum StageError {
+	// Download or extraction problems
+	Failed(String),
+	// The release itself is unfit for installation
+	Rejected(Vec<String>),
+}
+
+struct Staged {
+	rewrites: Vec<Rewrite>,
+	skipped: Vec<String>,
+	// Globs of files whose local edits survive updates
+	user_owned: Vec<String>,
+	// Downloaded .gma to install untouched instead of the staged tree
+	gma: Option<PathBuf>,
+}
+
+fn validate_lua(staging_dir: &Path) -> Result<(), StageError> {
+	print_log("Validating Lua files...");
+
+	let syntax_errors = lua_syntax::validate_tree(staging_dir);
+	if !syntax_errors.is_empty() {
+		return Err(StageError::Rejected(
+			syntax_errors.iter().map(|e| format!("Lua syntax error in {}", e)).collect(),
+		));
+	}
+	Ok(())
+}
+
+// Downloads a release and prepares it in `staging_dir` exactly as it will be installed
+fn stage_release(client: &Client, config: &Config, release: &Release, staging_dir: &Path) -> Result<Staged, StageError> {
+	print_log("Downloading latest version...");
+
+	// Extract into a staging directory so the active install stays untouched until validated
+	let _ = fs::remove_dir_all(staging_dir);
+
+	let gma_asset = config
+		.addon
+		.gma_asset
+		.as_ref()
+		.and_then(|name| release.assets.iter().find(|asset| &asset.name == name));
+
+	if gma_asset.is_none()
+		&& config.addon.git.is_none()
+		&& let Some(name) = &config.addon.gma_asset
+	{
+		print_log(&format!("Release has no {} asset", name));
+	}
+
+	// Docs, tests and repository files are dropped while extracting, before anything else looks at the tree
+	let mut skipped = Vec::new();
+	let mut keep = filter::keep(&config.addon.include, &config.addon.exclude, &mut skipped);
+
+	if let Some(source) = &config.addon.git {
+		print_log(&format!("Checking out {}...", release.tag_name));
+		git_source::checkout(source, &release.tag_name, staging_dir, &mut keep).map_err(StageError::Failed)?;
+	} else if let Some(asset) = gma_asset {
+		let gma_path = PathBuf::from(GMA_DOWNLOAD_PATH);
+		download_archive(client, &asset.browser_download_url, &gma_path, b"GMAD").map_err(StageError::Failed)?;
+
+		print_log(&format!("Extracting {}...", asset.name));
+
+		// The packed file is installed as published, so filters, renames and rewrites can't apply
+		let as_is = config.addon.gma_install == GmaInstall::AsIs;
+		let extracted = if as_is { gma::extract(&gma_path, staging_dir, &mut |_| true) } else { gma::extract(&gma_path, staging_dir, &mut keep) };
+		if let Err(e) = extracted {
+			let _ = fs::remove_file(&gma_path);
+			return Err(StageError::Failed(format!("Error: {}", e)));
 		}
-	};
 
-	let mut archive = match ZipArchive::new(file) {
-		Ok(a) => a,
-		Err(e) => {
-			print_log(&format!("Failed to read zip archive: {:?}", e));
-			// Clean up the invalid zip file
-			let _ = fs::remove_file(&zip_path);
-			return 1;
+		if as_is {
+			if let Err(e) = validate_lua(staging_dir) {
+				let _ = fs::remove_file(&gma_path);
+				return Err(e);
+			}
+			return Ok(Staged { rewrites: Vec::new(), skipped: Vec::new(), user_owned: Vec::new(), gma: Some(gma_path) });
 		}
+		let _ = fs::remove_file(&gma_path);
+	} else if let Some(asset) = release.assets.iter().find(|asset| asset.name == config.addon.addon_asset) {
+		download_zip(client, &asset.browser_download_url, staging_dir, &mut keep).map_err(StageError::Failed)?;
+	} else {
+		print_log(&format!("Release has no {} asset, using the source archive", config.addon.addon_asset));
+
+		// Download source code archive from GitHub with timeout
+		let download_url = format!("https://github.com/gmod-integration/gmod-integration/archive/refs/tags/{}.zip", release.tag_name);
+		download_zip(client, &download_url, staging_dir, &mut keep).map_err(StageError::Failed)?;
+	}
+
+	drop(keep);
+	if !skipped.is_empty() {
+		print_log(&format!("Skipped {} file(s) excluded by the content filters", skipped.len()));
+	}
+
+	let release_manifest = fetch_release_manifest(client, release, &config.addon.manifest_asset).unwrap_or_default();
+
+	// Rename rules: local config first, then the release manifest, then the built-in defaults
+	let rules = match config.addon.renames.clone() {
+		Some(rules) => rules,
+		None => release_manifest
+			.renames
+			.inspect(|rules| print_log(&format!("Using {} rename rules from release manifest", rules.len())))
+			.unwrap_or_else(config::default_renames),
 	};
+	let rules = rename::resolve_rules(&rules, &config.addon.folder);
+
+	rename::apply(staging_dir, &rules);
+
+	let leftovers = rename::find_unprefixed(staging_dir, &rules);
+	if !leftovers.is_empty() {
+		return Err(StageError::Rejected(
+			leftovers
+				.iter()
+				.map(|path| format!("{}: un-prefixed path would collide with a workshop copy", path.display()))
+				.collect(),
+		));
+	}
+
+	// Point include()/AddCSLuaFile() at the renamed folders
+	let rewrites = rewrite::rewrite_tree(staging_dir, &rewrite::lua_prefixes(&rules));
+	for change in &rewrites {
+		print_log(&format!(
+			"Rewrote include path in {}:{}: {} -> {}",
+			change.file.display(),
+			change.line,
+			change.from,
+			change.to
+		));
+	}
+	if !rewrites.is_empty() {
+		print_log(&format!("Rewrote {} include path(s)", rewrites.len()));
+	}
 
-	// Extract ZIP archive to addon directory
-	let target_dir = PathBuf::from("./garrysmod/addons/_gmod_integration_latest");
+	validate_lua(staging_dir)?;
 
-	if target_dir.exists() {
-		let _ = fs::remove_dir_all(&target_dir);
+	let user_owned = user_owned_patterns(config, &release_manifest.user_owned);
+
+	Ok(Staged { rewrites, skipped, user_owned, gma: None })
+}
+
+// The configured `user_owned` globs followed by `declared`, with `{folder}` filled in
+fn user_owned_patterns(config: &Config, declared: &[String]) -> Vec<String> {
+	config.addon.user_owned.iter().chain(declared).map(|pattern| pattern.replace("{folder}", &config.addon.folder)).collect()
+}
+
+// Moves a downloaded .gma into place, replacing the installed one atomically
+fn place_gma(from: &Path, to: &Path) -> Result<(), String> {
+	if fs::rename(from, to).is_ok() {
+		return Ok(());
 	}
 
-	if let Err(e) = fs::create_dir_all(&target_dir) {
-		print_log(&format!("Failed to create installation directory: {:?}", e));
-		return 1;
+	let tmp = to.with_extension("tmp");
+	let result = fs::copy(from, &tmp)
+		.and_then(|_| fs::rename(&tmp, to))
+		.map_err(|e| format!("Failed to install {}: {}", to.display(), e));
+	let _ = fs::remove_file(from);
+	result
+}
+
+// Logs why a staged release can't be installed, records it and discards the staging tree
+fn reject_update(version: &str, previous_version: Option<&String>, errors: Vec<String>, staging: &Path) {
+	for error in &errors {
+		print_log(error);
 	}
+	print_log(&format!(
+		"Refusing to install {}: {} problem(s) found, keeping current version",
+		version,
+		errors.len()
+	));
 
-	// Clean up on extraction failure
-	for i in 0..archive.len() {
-		let mut file = match archive.by_index(i) {
-			Ok(f) => f,
-			Err(e) => {
-				print_log(&format!("Failed to read zip entry {}: {}", i, e));
-				let _ = fs::remove_dir_all(&target_dir);
-				let _ = fs::remove_file(&zip_path);
-				return 1;
-			}
-		};
-		
-		let out_path = target_dir.join(file.name());
+	let mut entry = HistoryEntry::new(version, previous_version, UpdateStatus::Rejected);
+	entry.errors = errors;
+	history::record(entry);
 
-		// Prevent directory traversal attacks
-		if !out_path.starts_with(&target_dir) {
-			print_log(&format!("Suspicious file path in zip: {}", file.name()));
-			let _ = fs::remove_dir_all(&target_dir);
-			let _ = fs::remove_file(&zip_path);
-			return 1;
+	let _ = fs::remove_dir_all(staging);
+}
+
+// Checks the installed files against the install manifest and repairs them if configured to
+fn verify_install(client: &Client, config: &Config, release: &Release) {
+	if config.addon.verify == VerifyMode::Off {
+		return;
+	}
+
+	let Some(mut installed) = manifest::load().filter(|m| m.version == release.tag_name) else {
+		print_log("No install manifest for this version, skipping verification");
+		return;
+	};
+
+	let addon_dir = Path::new(ADDONS_DIR).join(&config.addon.folder);
+	let gma_path = Path::new(ADDONS_DIR).join(format!("{}.gma", config.addon.folder));
+	let packed = installed.packed.is_some();
+
+	// A packed install is a single file and is checked as a whole
+	let drift = if packed {
+		let mut drift = Drift::default();
+		let intact = installed
+			.packed
+			.as_ref()
+			.is_some_and(|expected| manifest::hash_file(&gma_path).is_ok_and(|actual| &actual == expected));
+		if !intact {
+			drift.modified.push(gma_path.display().to_string());
 		}
+		drift
+	} else {
+		manifest::verify(&addon_dir, &installed, &user_owned_patterns(config, &[]))
+	};
 
-		if file.is_dir() {
-			let _ = fs::create_dir_all(&out_path);
-		} else {
-			if let Some(parent) = out_path.parent() {
-				if let Err(e) = fs::create_dir_all(parent) {
-					print_log(&format!("Failed to create directory: {}", e));
-					continue;
-				}
-			}
-			
-			match fs::File::create(&out_path) {
-				Ok(mut out_file) => {
-					if let Err(e) = copy(&mut file, &mut out_file) {
-						print_log(&format!("Failed to extract file {}: {}", out_path.display(), e));
-					}
-				}
-				Err(e) => {
-					print_log(&format!("Failed to create file {}: {}", out_path.display(), e));
-				}
+	if drift.is_clean() {
+		print_log("Installed files match the install manifest");
+		return;
+	}
+
+	manifest::log_drift(&drift);
+
+	if config.addon.verify != VerifyMode::Repair {
+		print_log("Set addon.verify to \"repair\" to restore the damaged files");
+		return;
+	}
+
+	print_log(&format!("Repairing from {}...", release.tag_name));
+
+	let staging_dir = PathBuf::from(STAGING_DIR);
+	match stage_release(client, config, release, &staging_dir) {
+		Ok(staged) if packed => match staged.gma.map_or_else(
+			|| gma::write(&staging_dir, &gma_path, &config.addon.folder).map(|_| ()),
+			|gma| place_gma(&gma, &gma_path),
+		) {
+			Ok(()) => {
+				installed.packed = manifest::hash_file(&gma_path).ok();
+				manifest::save(&installed);
+				print_log(&format!("Repacked {}", gma_path.display()));
 			}
+			Err(e) => print_log(&format!("Repair failed: {}", e)),
+		},
+		Ok(_) => {
+			let repaired = manifest::restore(&staging_dir, &addon_dir, &installed, &drift);
+			print_log(&format!("Repaired {} file(s)", repaired));
+		}
+		Err(StageError::Failed(e)) => print_log(&format!("Repair failed: {}", e)),
+		Err(StageError::Rejected(errors)) => {
+			print_log(&format!("Repair failed: release rejected ({})", errors.join("; ")))
 		}
 	}
 
-	print_log("Installing update...");
+	let _ = fs::remove_dir_all(&staging_dir);
+}
 
-	// Verify extraction was successful
-	let extracted_root = match fs::read_dir(&target_dir)
-		.map_err(|e| format!("Failed to read target dir: {}", e))
-		.and_then(|mut entries| entries.next().ok_or("No extracted folder found".to_string()))
-		.and_then(|entry| entry.map_err(|e| format!("Failed to read entry: {}", e)))
-	{
-		Ok(entry) => entry.path(),
+// Fetches the latest release of the addon from GitHub
+fn fetch_latest_release(client: &Client) -> Result<Release, String> {
+	let res = client
+		.get("https://api.github.com/repos/gmod-integration/gmod-integration/releases/latest")
+		.header("User-Agent", "Gmod-Integration-Updater")
+		.timeout(Duration::from_secs(30))
+		.send()
+		.map_err(|e| format!("Failed to fetch release info: {:?}", e))?;
+
+	// Check response status
+	if !res.status().is_success() {
+		return Err(format!("API request failed with status: {}", res.status()));
+	}
+
+	let release: Release = res.json().map_err(|e| format!("Failed to parse release data: {:?}", e))?;
+
+	// Validate release data
+	if release.tag_name.is_empty() {
+		return Err("Invalid release: empty tag name".to_string());
+	}
+
+	Ok(release)
+}
+
+fn update_addon(client: &Client, config: &Config, version_cache: &mut VersionCache) -> i32 {
+	print_log("Checking Gmod Integration...");
+
+	// A git source installs whatever commit its ref points to, the SHA takes the place of the tag
+	let release = match &config.addon.git {
+		Some(source) => git_source::resolve(source).map(|commit| {
+			print_log(&format!("{} of {} is at {}", source.reference, source.repo, commit));
+			Release { tag_name: commit, assets: Vec::new(), draft: false, prerelease: false }
+		}),
+		None => fetch_latest_release(client),
+	};
+	let release = match release {
+		Ok(release) => release,
 		Err(e) => {
-			print_log(&format!("Error: {}", e));
-			let _ = fs::remove_dir_all(&target_dir);
-			let _ = fs::remove_file(&zip_path);
+			print_log(&e);
 			return 1;
 		}
 	};
 
-	// Move files from the GitHub-generated folder to the target directory
-	for entry in fs::read_dir(&extracted_root).expect("Failed to read extracted content") {
-		let entry = entry.expect("Failed to read entry");
-		let from = entry.path();
-		let to = target_dir.join(entry.file_name());
+	// Check if addon folder exists and version matches
+	let addon_dir = Path::new(ADDONS_DIR).join(&config.addon.folder);
+	let gma_path = Path::new(ADDONS_DIR).join(format!("{}.gma", config.addon.folder));
+	// Look for what was actually installed: a release without the configured .gma asset is
+	// installed as a folder even when the config asks for a packed install
+	let recorded = manifest::load();
+	let addon_exists = match &recorded {
+		Some(recorded) if recorded.packed.is_some() => gma_path.exists(),
+		Some(_) => addon_dir.exists(),
+		None => gma_path.exists() || addon_dir.exists(),
+	};
 
-		let _ = fs::rename(&from, &to);
+	if let Some(current) = &version_cache.gmod_integration
+		&& current == &release.tag_name
+		&& addon_exists
+	{
+		print_log(&format!("Gmod integration is up to date ({})", release.tag_name));
+		verify_install(client, config, &release);
+		return 0;
 	}
 
-	let _ = fs::remove_dir_all(&extracted_root);
-	let _ = fs::remove_dir_all(target_dir.join(".git"));
-	let _ = fs::remove_dir_all(target_dir.join(".github"));
-	let _ = fs::remove_file(&zip_path);
+	if !addon_exists {
+		print_log("Addon folder missing, downloading...");
+	} else {
+		print_log("Version mismatch, updating...");
+	}
 
-	// Rename files and folders to use _gmod_integration_latest prefix
-	let old_lua_path = target_dir.join("lua/autorun/gmod_integration.lua");
-	let new_lua_path = target_dir.join("lua/autorun/_gmod_integration_latest.lua");
-	
-	if old_lua_path.exists() {
-		if let Err(e) = fs::rename(&old_lua_path, &new_lua_path) {
-			print_log(&format!("Failed to rename Lua file: {}", e));
-		} else {
-			print_log("Renamed gmod_integration.lua to _gmod_integration_latest.lua");
+	let staging_dir = PathBuf::from(STAGING_DIR);
+	let staged = match stage_release(client, config, &release, &staging_dir) {
+		Ok(staged) => staged,
+		Err(StageError::Failed(e)) => {
+			print_log(&e);
+			let _ = fs::remove_dir_all(&staging_dir);
+			return 1;
 		}
-	}
+		Err(StageError::Rejected(errors)) => {
+			reject_update(&release.tag_name, version_cache.gmod_integration.as_ref(), errors, &staging_dir);
+			return 1;
+		}
+	};
 
-	// Rename the main folder if it exists
-	let old_folder_path = target_dir.join("gmod_integration");
-	let new_folder_path = target_dir.join("_gmod_integration_latest");
-	
-	if old_folder_path.exists() {
-		if let Err(e) = fs::rename(&old_folder_path, &new_folder_path) {
-			print_log(&format!("Failed to rename main folder: {}", e));
-		} else {
-			print_log("Renamed gmod_integration folder to _gmod_integration_latest");
+	print_log("Installing update...");
+
+	let installed = recorded.filter(|_| addon_exists);
+
+	// Local edits of user-owned files replace their upstream version in the staged tree
+	let preserved = user_files::preserve(&staging_dir, &addon_dir, &staged.user_owned, installed.as_ref());
+
+	let staged_files = match manifest::scan(&staging_dir) {
+		Ok(files) => files,
+		Err(e) => {
+			print_log(&format!("Failed to hash staged files: {}", e));
+			let _ = fs::remove_dir_all(&staging_dir);
+			return 1;
 		}
-	}
+	};
 
-	// Rename the lua/gmod_integration folder to lua/_gmod_integration_latest
-	let old_lua_folder_path = target_dir.join("lua/gmod_integration");
-	let new_lua_folder_path = target_dir.join("lua/_gmod_integration_latest");
-	
-	if old_lua_folder_path.exists() {
-		if let Err(e) = fs::rename(&old_lua_folder_path, &new_lua_folder_path) {
-			print_log(&format!("Failed to rename lua/gmod_integration folder: {}", e));
-		} else {
-			print_log("Renamed lua/gmod_integration folder to lua/_gmod_integration_latest");
+	// The release may lack the .gma asset, in which case the source archive is packed or installed as a folder
+	let packed = staged.gma.is_some() || config.addon.format == config::InstallFormat::Gma;
+
+	// Only touch changed files when we know what is installed, otherwise swap the whole folder
+	let activated = match (&staged.gma, &installed) {
+		(Some(gma), _) => place_gma(gma, &gma_path).map(|()| {
+			print_log(&format!("Installed {}", gma_path.display()));
+		}),
+		_ if packed => gma::write(&staging_dir, &gma_path, &config.addon.folder).map(|count| {
+			print_log(&format!("Packed {} file(s) into {}", count, gma_path.display()));
+		}),
+		(None, Some(installed)) => {
+			let changes = manifest::diff(&addon_dir, installed, &staged_files);
+			print_log(&format!(
+				"Applying changes: {} added, {} modified, {} removed, {} unchanged",
+				changes.added.len(),
+				changes.modified.len(),
+				changes.removed.len(),
+				changes.unchanged
+			));
+			manifest::apply_changes(&staging_dir, &addon_dir, &changes)
 		}
+		(None, None) => activate_staged(&staging_dir, &addon_dir),
+	};
+	let _ = fs::remove_dir_all(&staging_dir);
+
+	if let Err(e) = activated {
+		print_log(&format!("Failed to activate update: {}", e));
+
+		let mut entry = HistoryEntry::new(&release.tag_name, version_cache.gmod_integration.as_ref(), UpdateStatus::Failed);
+		entry.errors.push(e);
+		history::record(entry);
+		return 1;
 	}
 
+	// Drop the copy left over from the other install format so the addon isn't mounted twice
+	if packed && addon_dir.exists() {
+		let _ = fs::remove_dir_all(&addon_dir);
+	} else if !packed && gma_path.exists() {
+		let _ = fs::remove_file(&gma_path);
+	}
+
+	manifest::save(&InstallManifest {
+		version: release.tag_name.clone(),
+		files: staged_files,
+		packed: packed.then(|| manifest::hash_file(&gma_path).ok()).flatten(),
+		user_owned: staged.user_owned,
+	});
+	user_files::save_pristine(&preserved);
+
+	let mut entry = HistoryEntry::new(&release.tag_name, version_cache.gmod_integration.as_ref(), UpdateStatus::Installed);
+	entry.rewrites = staged.rewrites;
+	entry.skipped = staged.skipped;
+	entry.kept_local = preserved.kept;
+	history::record(entry);
+
 	// Update version cache and signal completion
 	version_cache.gmod_integration = Some(release.tag_name);
-	save_version_cache(&version_cache);
+	save_version_cache(version_cache);
 
 	// Signal to Lua that update completed
 	update_tmp_json();
@@ -469,6 +712,46 @@ fn gmod13_open(_lua: State) -> i32 {
 	0
 }
 
+#[gmod13_open]
+fn gmod13_open(_lua: State) -> i32 {
+	print_log("Starting auto-updater...");
+	if let Some(loader) = handshake::loader() {
+		print_log(&format!("Started by loader {} (ABI {})", loader.version, loader.abi));
+	}
+
+	let config = config::load_config();
+	let mut version_cache = load_version_cache();
+	let client = Client::builder()
+		.timeout(Duration::from_secs(30))
+		.build()
+		.unwrap_or_else(|_| Client::new());
+
+	// Clear out what earlier runs left behind
+	if config.gc.enabled {
+		gc::run(&config, version_cache.gmod_integration_active.as_deref());
+	}
+
+	// Update dependencies first
+	print_log("Checking dependencies...");
+	let mut releases = dependencies::ReleaseCache::default();
+	dependencies::update_all(&client, &config.dependencies.0, &mut version_cache.dependencies, &mut releases);
+
+	if config.inventory.enabled {
+		inventory::run(&client, &config, &version_cache.dependencies, &mut releases);
+	}
+
+	// Save dependency versions (even if some failed)
+	save_version_cache(&version_cache);
+
+	// Now update the main gmod integration addon
+	let status = update_addon(&client, &config, &mut version_cache);
+
+	// Other copies of the addon matter even when nothing was updated
+	conflicts::resolve(&config);
+
+	status
+}
+
 #[gmod13_close]
 fn gmod13_close(_: State) -> i32 {
 	0
@@ -0,0 +1,923 @@
+use std::{
+	fmt, fs,
+	path::{Path, PathBuf},
+};
+
+// Syntax checker for Garry's Mod Lua (Lua 5.1 / LuaJIT plus the GLua extensions:
+// `!=`, `!`, `&&`, `||`, `//` and `/* */` comments and `continue`).
+// It only answers "would this file compile", it does not build an AST.
+
+#[derive(Debug, Clone)]
+pub struct SyntaxError {
+	pub line: usize,
+	pub message: String,
+}
+
+impl fmt::Display for SyntaxError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "line {}: {}", self.line, self.message)
+	}
+}
+
+#[derive(Debug)]
+pub struct FileError {
+	pub path: PathBuf,
+	pub error: SyntaxError,
+}
+
+impl fmt::Display for FileError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}:{}: {}", self.path.display(), self.error.line, self.error.message)
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+	Name(String),
+	Keyword(&'static str),
+	Number,
+	Str,
+	Op(&'static str),
+	Eof,
+}
+
+impl fmt::Display for Tok {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Tok::Name(name) => write!(f, "'{}'", name),
+			Tok::Keyword(kw) => write!(f, "'{}'", kw),
+			Tok::Number => write!(f, "<number>"),
+			Tok::Str => write!(f, "<string>"),
+			Tok::Op(op) => write!(f, "'{}'", op),
+			Tok::Eof => write!(f, "<eof>"),
+		}
+	}
+}
+
+const KEYWORDS: &[&str] = &[
+	"and", "break", "continue", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in",
+	"local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+// Longest operators first so that greedy matching picks `...` over `..` over `.`
+const OPERATORS: &[&str] = &[
+	"...", "..", "==", "~=", "!=", "<=", ">=", "&&", "||", "::", "+", "-", "*", "/", "%", "^", "#", "<", ">", "=",
+	"(", ")", "{", "}", "[", "]", ";", ":", ",", ".", "!",
+];
+
+struct Lexer<'a> {
+	src: &'a [u8],
+	pos: usize,
+	line: usize,
+}
+
+impl<'a> Lexer<'a> {
+	fn new(src: &'a str) -> Self {
+		let mut lexer = Lexer { src: src.as_bytes(), pos: 0, line: 1 };
+		// Skip a shebang line like the reference implementation does
+		if lexer.src.starts_with(b"#") {
+			while lexer.pos < lexer.src.len() && lexer.src[lexer.pos] != b'\n' {
+				lexer.pos += 1;
+			}
+		}
+		lexer
+	}
+
+	fn peek(&self, offset: usize) -> u8 {
+		self.src.get(self.pos + offset).copied().unwrap_or(0)
+	}
+
+	fn error(&self, message: impl Into<String>) -> SyntaxError {
+		SyntaxError { line: self.line, message: message.into() }
+	}
+
+	fn bump(&mut self) -> u8 {
+		let c = self.peek(0);
+		self.pos += 1;
+		if c == b'\n' {
+			self.line += 1;
+		}
+		c
+	}
+
+	fn tokenize(mut self) -> Result<Vec<(Tok, usize)>, SyntaxError> {
+		let mut tokens = Vec::new();
+		loop {
+			self.skip_trivia()?;
+			let line = self.line;
+			if self.pos >= self.src.len() {
+				tokens.push((Tok::Eof, line));
+				return Ok(tokens);
+			}
+			let tok = self.next_token()?;
+			tokens.push((tok, line));
+		}
+	}
+
+	fn skip_trivia(&mut self) -> Result<(), SyntaxError> {
+		loop {
+			let c = self.peek(0);
+			if c == b' ' || c == b'\t' || c == b'\r' || c == b'\n' || c == 0x0b || c == 0x0c {
+				self.bump();
+			} else if c == b'-' && self.peek(1) == b'-' {
+				self.pos += 2;
+				if self.peek(0) == b'['
+					&& let Some(level) = self.long_bracket_level()
+				{
+					let start = self.line;
+					self.read_long_bracket(level)
+						.map_err(|_| SyntaxError { line: start, message: "unfinished long comment".into() })?;
+					continue;
+				}
+				self.skip_line();
+			} else if c == b'/' && self.peek(1) == b'/' {
+				self.skip_line();
+			} else if c == b'/' && self.peek(1) == b'*' {
+				let start = self.line;
+				self.pos += 2;
+				loop {
+					if self.pos >= self.src.len() {
+						return Err(SyntaxError { line: start, message: "unfinished block comment".into() });
+					}
+					if self.peek(0) == b'*' && self.peek(1) == b'/' {
+						self.pos += 2;
+						break;
+					}
+					self.bump();
+				}
+			} else {
+				return Ok(());
+			}
+		}
+	}
+
+	fn skip_line(&mut self) {
+		while self.pos < self.src.len() && self.peek(0) != b'\n' {
+			self.pos += 1;
+		}
+	}
+
+	// Returns the level of a long bracket (`[[` is 0, `[==[` is 2) starting at the cursor
+	fn long_bracket_level(&self) -> Option<usize> {
+		let mut level = 0;
+		while self.peek(1 + level) == b'=' {
+			level += 1;
+		}
+		(self.peek(1 + level) == b'[').then_some(level)
+	}
+
+	fn read_long_bracket(&mut self, level: usize) -> Result<(), ()> {
+		self.pos += level + 2;
+		loop {
+			if self.pos >= self.src.len() {
+				return Err(());
+			}
+			if self.peek(0) == b']' {
+				let mut closing = 0;
+				while self.peek(1 + closing) == b'=' {
+					closing += 1;
+				}
+				if closing == level && self.peek(1 + closing) == b']' {
+					self.pos += level + 2;
+					return Ok(());
+				}
+			}
+			self.bump();
+		}
+	}
+
+	fn next_token(&mut self) -> Result<Tok, SyntaxError> {
+		let c = self.peek(0);
+
+		if c.is_ascii_alphabetic() || c == b'_' || c >= 0x80 {
+			let start = self.pos;
+			while matches!(self.peek(0), c if c.is_ascii_alphanumeric() || c == b'_' || c >= 0x80) {
+				self.pos += 1;
+			}
+			let word = String::from_utf8_lossy(&self.src[start..self.pos]).into_owned();
+			return Ok(match KEYWORDS.iter().find(|kw| **kw == word) {
+				Some(kw) => Tok::Keyword(kw),
+				None => Tok::Name(word),
+			});
+		}
+
+		if c.is_ascii_digit() || (c == b'.' && self.peek(1).is_ascii_digit()) {
+			return self.read_number();
+		}
+
+		if c == b'"' || c == b'\'' {
+			return self.read_string(c);
+		}
+
+		if c == b'['
+			&& let Some(level) = self.long_bracket_level()
+		{
+			let start = self.line;
+			self.read_long_bracket(level)
+				.map_err(|_| SyntaxError { line: start, message: "unfinished long string".into() })?;
+			return Ok(Tok::Str);
+		}
+
+		for op in OPERATORS {
+			if self.src[self.pos..].starts_with(op.as_bytes()) {
+				self.pos += op.len();
+				return Ok(Tok::Op(op));
+			}
+		}
+
+		Err(self.error(format!("unexpected symbol near '{}'", char::from(c))))
+	}
+
+	fn read_number(&mut self) -> Result<Tok, SyntaxError> {
+		// Read greedily like llex.c and validate the whole lexeme afterwards
+		let start = self.pos;
+		let hex = self.peek(0) == b'0' && matches!(self.peek(1), b'x' | b'X');
+		let exponent: &[u8] = if hex { b"pP" } else { b"eE" };
+		loop {
+			let c = self.peek(0);
+			if exponent.contains(&c) && matches!(self.peek(1), b'+' | b'-') {
+				self.pos += 2;
+			} else if c.is_ascii_alphanumeric() || c == b'.' || c == b'_' {
+				self.pos += 1;
+			} else {
+				break;
+			}
+		}
+		let text = String::from_utf8_lossy(&self.src[start..self.pos]).to_ascii_lowercase();
+		if is_valid_number(&text) {
+			Ok(Tok::Number)
+		} else {
+			Err(self.error(format!("malformed number near '{}'", text)))
+		}
+	}
+
+	fn read_string(&mut self, quote: u8) -> Result<Tok, SyntaxError> {
+		self.pos += 1;
+		loop {
+			match self.peek(0) {
+				0 if self.pos >= self.src.len() => return Err(self.error("unfinished string")),
+				b'\n' | b'\r' => return Err(self.error("unfinished string")),
+				b'\\' => {
+					self.pos += 1;
+					match self.peek(0) {
+						b'\n' | b'\r' => {
+							// `\r\n` and `\n\r` are a single line break
+							let first = self.bump();
+							let next = self.peek(0);
+							if (next == b'\n' || next == b'\r') && next != first {
+								self.bump();
+							}
+						}
+						b'x' => {
+							self.pos += 1;
+							for _ in 0..2 {
+								if !self.peek(0).is_ascii_hexdigit() {
+									return Err(self.error("hexadecimal digit expected in escape sequence"));
+								}
+								self.pos += 1;
+							}
+						}
+						b'z' => {
+							self.pos += 1;
+							while self.peek(0).is_ascii_whitespace() {
+								self.bump();
+							}
+						}
+						c if c.is_ascii_digit() => {
+							let mut value = 0u32;
+							let mut digits = 0;
+							while digits < 3 && self.peek(0).is_ascii_digit() {
+								value = value * 10 + u32::from(self.peek(0) - b'0');
+								self.pos += 1;
+								digits += 1;
+							}
+							if value > 255 {
+								return Err(self.error("escape sequence too large"));
+							}
+						}
+						0 if self.pos >= self.src.len() => return Err(self.error("unfinished string")),
+						// LuaJIT accepts any other escaped character as itself
+						_ => self.pos += 1,
+					}
+				}
+				c if c == quote => {
+					self.pos += 1;
+					return Ok(Tok::Str);
+				}
+				_ => self.pos += 1,
+			}
+		}
+	}
+}
+
+fn is_valid_number(text: &str) -> bool {
+	// LuaJIT suffixes for 64-bit integer and imaginary literals
+	let body = text
+		.strip_suffix("ull")
+		.or_else(|| text.strip_suffix("ll"))
+		.or_else(|| text.strip_suffix('i'))
+		.unwrap_or(text);
+
+	if let Some(hex) = body.strip_prefix("0x") {
+		let (mantissa, exp) = match hex.split_once('p') {
+			Some((m, e)) => (m, Some(e)),
+			None => (hex, None),
+		};
+		let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+		return (!int.is_empty() || !frac.is_empty())
+			&& int.chars().all(|c| c.is_ascii_hexdigit())
+			&& frac.chars().all(|c| c.is_ascii_hexdigit())
+			&& exp.is_none_or(is_valid_exponent);
+	}
+
+	let (mantissa, exp) = match body.split_once('e') {
+		Some((m, e)) => (m, Some(e)),
+		None => (body, None),
+	};
+	let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+	(!int.is_empty() || !frac.is_empty())
+		&& int.chars().all(|c| c.is_ascii_digit())
+		&& frac.chars().all(|c| c.is_ascii_digit())
+		&& exp.is_none_or(is_valid_exponent)
+}
+
+fn is_valid_exponent(exp: &str) -> bool {
+	let digits = exp.strip_prefix(['+', '-']).unwrap_or(exp);
+	!digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+struct FunctionState {
+	vararg: bool,
+	loop_depth: usize,
+}
+
+// LuaJIT rejects chunks nested deeper than LJ_MAX_XLEVEL
+const MAX_DEPTH: usize = 200;
+
+struct Parser {
+	tokens: Vec<(Tok, usize)>,
+	pos: usize,
+	functions: Vec<FunctionState>,
+	// Blocks and expressions currently being parsed, bounds the recursion
+	depth: usize,
+}
+
+type ParseResult<T = ()> = Result<T, SyntaxError>;
+
+impl Parser {
+	fn current(&self) -> &Tok {
+		&self.tokens[self.pos].0
+	}
+
+	fn line(&self) -> usize {
+		self.tokens[self.pos].1
+	}
+
+	fn lookahead(&self) -> &Tok {
+		&self.tokens[(self.pos + 1).min(self.tokens.len() - 1)].0
+	}
+
+	fn advance(&mut self) {
+		if self.pos < self.tokens.len() - 1 {
+			self.pos += 1;
+		}
+	}
+
+	fn is_op(&self, op: &str) -> bool {
+		matches!(self.current(), Tok::Op(o) if *o == op)
+	}
+
+	fn is_keyword(&self, kw: &str) -> bool {
+		matches!(self.current(), Tok::Keyword(k) if *k == kw)
+	}
+
+	fn error(&self, message: impl fmt::Display) -> SyntaxError {
+		SyntaxError { line: self.line(), message: format!("{} near {}", message, self.current()) }
+	}
+
+	fn accept_op(&mut self, op: &str) -> bool {
+		let matched = self.is_op(op);
+		if matched {
+			self.advance();
+		}
+		matched
+	}
+
+	fn accept_keyword(&mut self, kw: &str) -> bool {
+		let matched = self.is_keyword(kw);
+		if matched {
+			self.advance();
+		}
+		matched
+	}
+
+	fn expect_op(&mut self, op: &str) -> ParseResult {
+		if self.accept_op(op) { Ok(()) } else { Err(self.error(format!("'{}' expected", op))) }
+	}
+
+	fn expect_keyword(&mut self, kw: &str) -> ParseResult {
+		if self.accept_keyword(kw) { Ok(()) } else { Err(self.error(format!("'{}' expected", kw))) }
+	}
+
+	// Like `expect_keyword`, but mentions the opening token when it is on another line
+	fn expect_closing(&mut self, kw: &str, opener: &str, line: usize) -> ParseResult {
+		if self.accept_keyword(kw) {
+			Ok(())
+		} else if line == self.line() {
+			Err(self.error(format!("'{}' expected", kw)))
+		} else {
+			Err(self.error(format!("'{}' expected (to close '{}' at line {})", kw, opener, line)))
+		}
+	}
+
+	fn expect_name(&mut self) -> ParseResult {
+		if matches!(self.current(), Tok::Name(_)) {
+			self.advance();
+			Ok(())
+		} else {
+			Err(self.error("<name> expected"))
+		}
+	}
+
+	fn function(&mut self) -> &mut FunctionState {
+		self.functions.last_mut().expect("function stack is never empty")
+	}
+
+	fn block_follows(&self) -> bool {
+		matches!(self.current(), Tok::Eof | Tok::Keyword("else" | "elseif" | "end" | "until"))
+	}
+
+	fn chunk(&mut self) -> ParseResult {
+		self.block()?;
+		if *self.current() != Tok::Eof {
+			return Err(self.error("'<eof>' expected"));
+		}
+		Ok(())
+	}
+
+	// Runs `parse` one nesting level deeper
+	fn nested(&mut self, parse: impl FnOnce(&mut Self) -> ParseResult) -> ParseResult {
+		if self.depth >= MAX_DEPTH {
+			return Err(self.error("chunk has too many syntax levels"));
+		}
+		self.depth += 1;
+		let result = parse(self);
+		self.depth -= 1;
+		result
+	}
+
+	fn block(&mut self) -> ParseResult {
+		self.nested(Self::block_statements)
+	}
+
+	fn block_statements(&mut self) -> ParseResult {
+		while !self.block_follows() {
+			if self.is_keyword("return") {
+				self.advance();
+				if !self.block_follows() && !self.is_op(";") {
+					self.expr_list()?;
+				}
+				self.accept_op(";");
+				if !self.block_follows() {
+					return Err(self.error("'end' expected"));
+				}
+				break;
+			}
+			self.statement()?;
+		}
+		Ok(())
+	}
+
+	fn loop_body(&mut self) -> ParseResult {
+		self.function().loop_depth += 1;
+		let result = self.block();
+		self.function().loop_depth -= 1;
+		result
+	}
+
+	fn statement(&mut self) -> ParseResult {
+		let line = self.line();
+		match self.current().clone() {
+			Tok::Op(";") => self.advance(),
+			Tok::Op("::") => {
+				self.advance();
+				self.expect_name()?;
+				self.expect_op("::")?;
+			}
+			Tok::Keyword(kw @ ("break" | "continue")) => {
+				if self.function().loop_depth == 0 {
+					return Err(self.error(format!("no loop to {}", kw)));
+				}
+				self.advance();
+			}
+			Tok::Keyword("goto") => {
+				self.advance();
+				self.expect_name()?;
+			}
+			Tok::Keyword("do") => {
+				self.advance();
+				self.block()?;
+				self.expect_closing("end", "do", line)?;
+			}
+			Tok::Keyword("while") => {
+				self.advance();
+				self.expr()?;
+				self.expect_keyword("do")?;
+				self.loop_body()?;
+				self.expect_closing("end", "while", line)?;
+			}
+			Tok::Keyword("repeat") => {
+				self.advance();
+				self.loop_body()?;
+				self.expect_closing("until", "repeat", line)?;
+				self.expr()?;
+			}
+			Tok::Keyword("if") => {
+				self.advance();
+				self.expr()?;
+				self.expect_keyword("then")?;
+				self.block()?;
+				while self.accept_keyword("elseif") {
+					self.expr()?;
+					self.expect_keyword("then")?;
+					self.block()?;
+				}
+				if self.accept_keyword("else") {
+					self.block()?;
+				}
+				self.expect_closing("end", "if", line)?;
+			}
+			Tok::Keyword("for") => {
+				self.advance();
+				self.expect_name()?;
+				if self.accept_op("=") {
+					self.expr()?;
+					self.expect_op(",")?;
+					self.expr()?;
+					if self.accept_op(",") {
+						self.expr()?;
+					}
+				} else {
+					while self.accept_op(",") {
+						self.expect_name()?;
+					}
+					if !self.accept_keyword("in") {
+						return Err(self.error("'=' or 'in' expected"));
+					}
+					self.expr_list()?;
+				}
+				self.expect_keyword("do")?;
+				self.loop_body()?;
+				self.expect_closing("end", "for", line)?;
+			}
+			Tok::Keyword("function") => {
+				self.advance();
+				self.expect_name()?;
+				while self.accept_op(".") {
+					self.expect_name()?;
+				}
+				if self.accept_op(":") {
+					self.expect_name()?;
+				}
+				self.function_body(line)?;
+			}
+			Tok::Keyword("local") => {
+				self.advance();
+				if self.accept_keyword("function") {
+					self.expect_name()?;
+					self.function_body(line)?;
+				} else {
+					self.expect_name()?;
+					while self.accept_op(",") {
+						self.expect_name()?;
+					}
+					if self.accept_op("=") {
+						self.expr_list()?;
+					}
+				}
+			}
+			_ => self.expression_statement()?,
+		}
+		Ok(())
+	}
+
+	fn expression_statement(&mut self) -> ParseResult {
+		let kind = self.suffixed_expr()?;
+		if self.is_op("=") || self.is_op(",") {
+			if kind == ExprKind::Call {
+				return Err(self.error("syntax error"));
+			}
+			while self.accept_op(",") {
+				if self.suffixed_expr()? == ExprKind::Call {
+					return Err(self.error("syntax error"));
+				}
+			}
+			self.expect_op("=")?;
+			self.expr_list()?;
+		} else if kind != ExprKind::Call {
+			return Err(self.error("syntax error"));
+		}
+		Ok(())
+	}
+
+	fn function_body(&mut self, line: usize) -> ParseResult {
+		self.expect_op("(")?;
+		let mut vararg = false;
+		if !self.is_op(")") {
+			loop {
+				if self.accept_op("...") {
+					vararg = true;
+					break;
+				}
+				self.expect_name()?;
+				if !self.accept_op(",") {
+					break;
+				}
+			}
+		}
+		self.expect_op(")")?;
+		self.functions.push(FunctionState { vararg, loop_depth: 0 });
+		let result = self.block().and_then(|_| self.expect_closing("end", "function", line));
+		self.functions.pop();
+		result
+	}
+
+	fn expr_list(&mut self) -> ParseResult {
+		self.expr()?;
+		while self.accept_op(",") {
+			self.expr()?;
+		}
+		Ok(())
+	}
+
+	// Operator precedence does not change whether a chunk is valid, so every
+	// binary operator is treated alike
+	fn expr(&mut self) -> ParseResult {
+		self.nested(Self::binary_expr)
+	}
+
+	fn binary_expr(&mut self) -> ParseResult {
+		loop {
+			while matches!(self.current(), Tok::Keyword("not") | Tok::Op("-" | "#" | "!")) {
+				self.advance();
+			}
+			self.simple_expr()?;
+			let binary = matches!(
+				self.current(),
+				Tok::Keyword("and" | "or")
+					| Tok::Op(
+						"+" | "-" | "*" | "/" | "%" | "^" | ".." | "==" | "~=" | "!=" | "<" | "<=" | ">" | ">="
+							| "&&" | "||"
+					)
+			);
+			if !binary {
+				return Ok(());
+			}
+			self.advance();
+		}
+	}
+
+	fn simple_expr(&mut self) -> ParseResult {
+		let line = self.line();
+		match self.current() {
+			Tok::Number | Tok::Str | Tok::Keyword("nil" | "true" | "false") => self.advance(),
+			Tok::Op("...") => {
+				if !self.function().vararg {
+					return Err(self.error("cannot use '...' outside a vararg function"));
+				}
+				self.advance();
+			}
+			Tok::Op("{") => self.table()?,
+			Tok::Keyword("function") => {
+				self.advance();
+				self.function_body(line)?;
+			}
+			_ => {
+				self.suffixed_expr()?;
+			}
+		}
+		Ok(())
+	}
+
+	fn primary_expr(&mut self) -> ParseResult {
+		match self.current() {
+			Tok::Name(_) => {
+				self.advance();
+				Ok(())
+			}
+			Tok::Op("(") => {
+				let line = self.line();
+				self.advance();
+				self.expr()?;
+				if self.accept_op(")") {
+					Ok(())
+				} else if line == self.line() {
+					Err(self.error("')' expected"))
+				} else {
+					Err(self.error(format!("')' expected (to close '(' at line {})", line)))
+				}
+			}
+			_ => Err(self.error("unexpected symbol")),
+		}
+	}
+
+	fn suffixed_expr(&mut self) -> ParseResult<ExprKind> {
+		self.primary_expr()?;
+		let mut kind = ExprKind::Value;
+		loop {
+			match self.current() {
+				Tok::Op(".") => {
+					self.advance();
+					self.expect_name()?;
+					kind = ExprKind::Value;
+				}
+				Tok::Op("[") => {
+					self.advance();
+					self.expr()?;
+					self.expect_op("]")?;
+					kind = ExprKind::Value;
+				}
+				Tok::Op(":") => {
+					self.advance();
+					self.expect_name()?;
+					self.call_args()?;
+					kind = ExprKind::Call;
+				}
+				Tok::Op("(" | "{") | Tok::Str => {
+					self.call_args()?;
+					kind = ExprKind::Call;
+				}
+				_ => return Ok(kind),
+			}
+		}
+	}
+
+	fn call_args(&mut self) -> ParseResult {
+		match self.current() {
+			Tok::Str => {
+				self.advance();
+				Ok(())
+			}
+			Tok::Op("{") => self.table(),
+			Tok::Op("(") => {
+				let line = self.line();
+				self.advance();
+				if !self.is_op(")") {
+					self.expr_list()?;
+				}
+				if self.accept_op(")") {
+					Ok(())
+				} else if line == self.line() {
+					Err(self.error("')' expected"))
+				} else {
+					Err(self.error(format!("')' expected (to close '(' at line {})", line)))
+				}
+			}
+			_ => Err(self.error("function arguments expected")),
+		}
+	}
+
+	fn table(&mut self) -> ParseResult {
+		let line = self.line();
+		self.expect_op("{")?;
+		while !self.is_op("}") {
+			if self.accept_op("[") {
+				self.expr()?;
+				self.expect_op("]")?;
+				self.expect_op("=")?;
+				self.expr()?;
+			} else if matches!(self.current(), Tok::Name(_)) && matches!(self.lookahead(), Tok::Op("=")) {
+				self.advance();
+				self.advance();
+				self.expr()?;
+			} else {
+				self.expr()?;
+			}
+			if !self.accept_op(",") && !self.accept_op(";") {
+				break;
+			}
+		}
+		if self.accept_op("}") {
+			Ok(())
+		} else if line == self.line() {
+			Err(self.error("'}' expected"))
+		} else {
+			Err(self.error(format!("'}}' expected (to close '{{' at line {})", line)))
+		}
+	}
+}
+
+#[derive(PartialEq)]
+enum ExprKind {
+	Value,
+	Call,
+}
+
+pub fn check(source: &str) -> Result<(), SyntaxError> {
+	let tokens = Lexer::new(source).tokenize()?;
+	let mut parser = Parser {
+		tokens,
+		pos: 0,
+		// The main chunk of a file is always a vararg function
+		functions: vec![FunctionState { vararg: true, loop_depth: 0 }],
+		depth: 0,
+	};
+	parser.chunk()
+}
+
+// Checks every .lua file below `root` and returns one error per broken file,
+// with paths relative to `root`
+pub fn validate_tree(root: &Path) -> Vec<FileError> {
+	let mut errors = Vec::new();
+	let mut pending = vec![root.to_path_buf()];
+
+	while let Some(dir) = pending.pop() {
+		let entries = match fs::read_dir(&dir) {
+			Ok(entries) => entries,
+			Err(e) => {
+				errors.push(FileError {
+					path: dir.strip_prefix(root).unwrap_or(&dir).to_path_buf(),
+					error: SyntaxError { line: 0, message: format!("failed to read directory: {}", e) },
+				});
+				continue;
+			}
+		};
+
+		for entry in entries.flatten() {
+			let path = entry.path();
+			if path.is_dir() {
+				pending.push(path);
+				continue;
+			}
+			if path.extension().and_then(|ext| ext.to_str()) != Some("lua") {
+				continue;
+			}
+
+			let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+			let result = fs::read(&path)
+				.map_err(|e| SyntaxError { line: 0, message: format!("failed to read file: {}", e) })
+				.and_then(|bytes| check(&String::from_utf8_lossy(&bytes)));
+			if let Err(error) = result {
+				errors.push(FileError { path: relative, error });
+			}
+		}
+	}
+
+	errors.sort_by(|a, b| a.path.cmp(&b.path));
+	errors
+}
+
+#[cfg(test)]
+mod tests {
+	use super::check;
+
+	#[test]
+	fn accepts_glua_operators() {
+		assert!(check("if a != b && !c || d then print(a) end").is_ok());
+		assert!(check("local x = !(a && b) || c != d").is_ok());
+	}
+
+	#[test]
+	fn accepts_glua_comments() {
+		assert!(check("// line comment\nlocal x = 1 // trailing").is_ok());
+		assert!(check("/* block\ncomment */ local x = 1 /* inline */ + 2").is_ok());
+		assert!(check("/* unfinished").is_err());
+	}
+
+	#[test]
+	fn accepts_continue_in_loops_only() {
+		assert!(check("for i = 1, 10 do if i % 2 == 0 then continue end print(i) end").is_ok());
+		assert!(check("while true do continue end").is_ok());
+		assert!(check("continue").is_err());
+		assert!(check("for i = 1, 2 do local f = function() continue end end").is_err());
+	}
+
+	#[test]
+	fn accepts_escaped_line_breaks() {
+		assert!(check("local s = \"a\\\nb\"").is_ok());
+		assert!(check("local s = \"a\\\r\nb\"\r\nprint(s)\r\n").is_ok());
+		assert!(check("local s = \"a\\\n\rb\"").is_ok());
+		assert!(check("local s = \"a\\\r\n\nb\"").is_err());
+	}
+
+	#[test]
+	fn counts_crlf_lines_once() {
+		let error = check("local s = \"a\\\r\nb\"\r\nlocal = 1").unwrap_err();
+		assert_eq!(error.line, 3);
+	}
+
+	#[test]
+	fn rejects_deep_nesting() {
+		let nested = |levels: usize| format!("x = {}1{}", "(".repeat(levels), ")".repeat(levels));
+		assert!(check(&nested(100)).is_ok());
+		assert!(check(&nested(1000)).is_err());
+		assert!(check(&format!("{}{}", "do ".repeat(1000), "end ".repeat(1000))).is_err());
+		assert!(check(&format!("x = {}{}", "{".repeat(1000), "}".repeat(1000))).is_err());
+	}
+
+	#[test]
+	fn rejects_unfinished_strings() {
+		assert!(check("local s = \"a\nb\"").is_err());
+		assert!(check("local s = 'a\r\nb'").is_err());
+	}
+}
@@ -0,0 +1,286 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+	collections::BTreeMap,
+	fs, io,
+	path::Path,
+};
+
+use crate::{print_log, user_files};
+
+const MANIFEST_FILE: &str = "garrysmod/data/gm_integration/install_manifest.json";
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct FileRecord {
+	pub size: u64,
+	pub sha256: String,
+}
+
+// Every file that belongs to the installed addon, keyed by `/`-separated relative path
+#[derive(Deserialize, Serialize, Debug)]
+pub struct InstallManifest {
+	pub version: String,
+	pub files: BTreeMap<String, FileRecord>,
+	// The .gma file when the addon is installed packed
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub packed: Option<FileRecord>,
+	// Globs of files operators may edit, left alone by verification
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub user_owned: Vec<String>,
+}
+
+#[derive(Default, Debug)]
+pub struct Drift {
+	pub modified: Vec<String>,
+	pub missing: Vec<String>,
+	pub extra: Vec<String>,
+}
+
+impl Drift {
+	pub fn is_clean(&self) -> bool {
+		self.modified.is_empty() && self.missing.is_empty() && self.extra.is_empty()
+	}
+}
+
+pub fn hash_file(path: &Path) -> io::Result<FileRecord> {
+	let mut file = fs::File::open(path)?;
+	let mut hasher = Sha256::new();
+	let size = io::copy(&mut file, &mut hasher)?;
+	Ok(FileRecord { size, sha256: format!("{:x}", hasher.finalize()) })
+}
+
+pub fn hash_bytes(data: &[u8]) -> String {
+	format!("{:x}", Sha256::digest(data))
+}
+
+// Lists all files below `root` as `/`-separated relative paths
+pub fn list_files(root: &Path) -> Vec<String> {
+	let mut files = Vec::new();
+	let mut pending = vec![root.to_path_buf()];
+
+	while let Some(dir) = pending.pop() {
+		let Ok(entries) = fs::read_dir(&dir) else {
+			continue;
+		};
+
+		for entry in entries.flatten() {
+			let path = entry.path();
+			if path.is_dir() {
+				pending.push(path);
+			} else if let Ok(relative) = path.strip_prefix(root) {
+				let parts: Vec<_> = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect();
+				files.push(parts.join("/"));
+			}
+		}
+	}
+
+	files.sort();
+	files
+}
+
+pub fn scan(root: &Path) -> io::Result<BTreeMap<String, FileRecord>> {
+	list_files(root)
+		.into_iter()
+		.map(|relative| hash_file(&root.join(&relative)).map(|record| (relative, record)))
+		.collect()
+}
+
+pub fn load() -> Option<InstallManifest> {
+	fs::read_to_string(MANIFEST_FILE)
+		.ok()
+		.and_then(|content| serde_json::from_str(&content).ok())
+}
+
+pub fn save(manifest: &InstallManifest) {
+	if let Some(parent) = Path::new(MANIFEST_FILE).parent() {
+		let _ = fs::create_dir_all(parent);
+	}
+
+	let result = serde_json::to_string_pretty(manifest)
+		.map_err(|e| e.to_string())
+		.and_then(|content| fs::write(MANIFEST_FILE, content).map_err(|e| e.to_string()));
+	if let Err(e) = result {
+		print_log(&format!("Failed to save install manifest: {}", e));
+	}
+}
+
+// Files matching the recorded or the configured `user_owned` globs belong to the operator and
+// are never reported
+pub fn verify(root: &Path, manifest: &InstallManifest, user_owned: &[String]) -> Drift {
+	let mut drift = Drift::default();
+	let is_user_owned =
+		|relative: &str| user_files::is_user_owned(relative, &manifest.user_owned) || user_files::is_user_owned(relative, user_owned);
+
+	for (relative, expected) in &manifest.files {
+		if is_user_owned(relative) {
+			continue;
+		}
+
+		match hash_file(&root.join(relative)) {
+			Ok(actual) if &actual == expected => {}
+			Ok(_) => drift.modified.push(relative.clone()),
+			Err(_) => drift.missing.push(relative.clone()),
+		}
+	}
+
+	drift.extra = list_files(root)
+		.into_iter()
+		.filter(|relative| !manifest.files.contains_key(relative) && !is_user_owned(relative))
+		.collect();
+	drift
+}
+
+pub fn log_drift(drift: &Drift) {
+	for relative in &drift.modified {
+		print_log(&format!("Modified: {}", relative));
+	}
+	for relative in &drift.missing {
+		print_log(&format!("Missing: {}", relative));
+	}
+	for relative in &drift.extra {
+		print_log(&format!("Not part of the install: {}", relative));
+	}
+	print_log(&format!(
+		"Installed addon differs from its manifest: {} modified, {} missing, {} extra",
+		drift.modified.len(),
+		drift.missing.len(),
+		drift.extra.len()
+	));
+}
+
+// Copies damaged files back from a freshly staged copy of the same release and
+// removes files that aren't part of it
+pub fn restore(staging: &Path, root: &Path, manifest: &InstallManifest, drift: &Drift) -> usize {
+	let mut repaired = 0;
+
+	for relative in drift.modified.iter().chain(&drift.missing) {
+		let from = staging.join(relative);
+		let to = root.join(relative);
+
+		match hash_file(&from) {
+			Ok(record) if Some(&record) != manifest.files.get(relative) => {
+				print_log(&format!("Warning: upstream copy of {} no longer matches the manifest", relative));
+			}
+			Ok(_) => {}
+			Err(_) => {
+				print_log(&format!("Cannot repair {}: not found in release", relative));
+				continue;
+			}
+		}
+
+		if let Some(parent) = to.parent() {
+			let _ = fs::create_dir_all(parent);
+		}
+		match fs::copy(&from, &to) {
+			Ok(_) => {
+				print_log(&format!("Restored {}", relative));
+				repaired += 1;
+			}
+			Err(e) => print_log(&format!("Failed to restore {}: {}", relative, e)),
+		}
+	}
+
+	for relative in &drift.extra {
+		match fs::remove_file(root.join(relative)) {
+			Ok(()) => {
+				print_log(&format!("Removed {}", relative));
+				repaired += 1;
+			}
+			Err(e) => print_log(&format!("Failed to remove {}: {}", relative, e)),
+		}
+	}
+
+	repaired
+}
+
+// Accepts the current content of `paths` (files or folders) as part of the install,
+// for files the updater itself places into the addon after installation
+pub fn refresh(root: &Path, paths: &[String]) {
+	let Some(mut manifest) = load() else {
+		return;
+	};
+
+	for relative in paths {
+		let path = root.join(relative);
+		let files = if path.is_dir() {
+			list_files(&path).into_iter().map(|inner| format!("{}/{}", relative, inner)).collect()
+		} else {
+			vec![relative.clone()]
+		};
+
+		for file in files {
+			if let Ok(record) = hash_file(&root.join(&file)) {
+				manifest.files.insert(file, record);
+			}
+		}
+	}
+
+	save(&manifest);
+}
+
+#[derive(Default, Debug)]
+pub struct Changes {
+	pub added: Vec<String>,
+	pub modified: Vec<String>,
+	pub removed: Vec<String>,
+	pub unchanged: usize,
+}
+
+// Compares the installed manifest with a staged release. A file only counts as
+// unchanged when the live copy still matches the manifest too
+pub fn diff(root: &Path, installed: &InstallManifest, staged: &BTreeMap<String, FileRecord>) -> Changes {
+	let mut changes = Changes::default();
+
+	for (relative, record) in staged {
+		match installed.files.get(relative) {
+			None => changes.added.push(relative.clone()),
+			Some(old) if old == record && hash_file(&root.join(relative)).is_ok_and(|live| &live == record) => {
+				changes.unchanged += 1;
+			}
+			Some(_) => changes.modified.push(relative.clone()),
+		}
+	}
+
+	changes.removed = installed.files.keys().filter(|relative| !staged.contains_key(*relative)).cloned().collect();
+	changes
+}
+
+fn remove_empty_parents(root: &Path, relative: &str) {
+	let mut dir = root.join(relative);
+	while dir.pop() && dir.starts_with(root) && dir != root {
+		if fs::remove_dir(&dir).is_err() {
+			break;
+		}
+	}
+}
+
+// Moves only added and modified files from `staging` into `root` and deletes removed ones,
+// leaving unchanged files (and their mtimes) alone
+pub fn apply_changes(staging: &Path, root: &Path, changes: &Changes) -> Result<(), String> {
+	for relative in changes.added.iter().chain(&changes.modified) {
+		let from = staging.join(relative);
+		let to = root.join(relative);
+
+		if let Some(parent) = to.parent() {
+			fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+		}
+
+		// A rename replaces the file atomically, copy when staging is on another filesystem
+		if fs::rename(&from, &to).is_err() {
+			let tmp = to.with_extension("tmp");
+			fs::copy(&from, &tmp)
+				.and_then(|_| fs::rename(&tmp, &to))
+				.map_err(|e| format!("Failed to update {}: {}", relative, e))?;
+		}
+	}
+
+	for relative in &changes.removed {
+		match fs::remove_file(root.join(relative)) {
+			Ok(()) => remove_empty_parents(root, relative),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => return Err(format!("Failed to remove {}: {}", relative, e)),
+		}
+	}
+
+	Ok(())
+}
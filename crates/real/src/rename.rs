@@ -0,0 +1,104 @@
+use std::{
+	collections::HashSet,
+	fs,
+	path::{Component, Path, PathBuf},
+};
+
+use crate::{config::RenameRule, print_log};
+
+fn is_safe_relative(path: &str) -> bool {
+	!path.is_empty() && Path::new(path).components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+// Expands `{folder}` in the rule targets and drops rules that could escape the addon directory
+pub fn resolve_rules(rules: &[RenameRule], folder: &str) -> Vec<RenameRule> {
+	rules
+		.iter()
+		.filter_map(|rule| {
+			let resolved = RenameRule { to: rule.to.replace("{folder}", folder), ..rule.clone() };
+			if is_safe_relative(&resolved.from) && is_safe_relative(&resolved.to) {
+				Some(resolved)
+			} else {
+				print_log(&format!("Ignoring unsafe rename rule {} -> {}", rule.from, rule.to));
+				None
+			}
+		})
+		.collect()
+}
+
+pub fn apply(dir: &Path, rules: &[RenameRule]) {
+	for rule in rules {
+		let from = dir.join(&rule.from);
+		let to = dir.join(&rule.to);
+
+		if !from.exists() {
+			if !rule.optional {
+				print_log(&format!("Warning: expected {} in release, not renamed", rule.from));
+			}
+			continue;
+		}
+
+		if let Some(parent) = to.parent() {
+			let _ = fs::create_dir_all(parent);
+		}
+
+		if let Err(e) = fs::rename(&from, &to) {
+			print_log(&format!("Failed to rename {}: {}", rule.from, e));
+		} else {
+			print_log(&format!("Renamed {} to {}", rule.from, rule.to));
+		}
+	}
+}
+
+// Lists leftovers that still carry an un-prefixed name from the rules where they would collide
+// with a workshop copy: the original paths of the rules, and same-named files or folders directly
+// in `lua/` or `lua/autorun/`, which every mounted addon shares. Anything inside a renamed target is
+// already namespaced
+pub fn find_unprefixed(dir: &Path, rules: &[RenameRule]) -> Vec<PathBuf> {
+	let renamed: Vec<_> = rules.iter().filter(|rule| rule.from != rule.to).collect();
+	let names: HashSet<_> = renamed
+		.iter()
+		.filter_map(|rule| Path::new(&rule.from).file_name().map(|name| name.to_os_string()))
+		.collect();
+	let targets: Vec<PathBuf> = renamed.iter().map(|rule| PathBuf::from(&rule.to)).collect();
+	let is_renamed = |path: &Path| targets.iter().any(|target| path.starts_with(target));
+
+	let mut found: Vec<PathBuf> = renamed
+		.iter()
+		.map(|rule| PathBuf::from(&rule.from))
+		.filter(|from| dir.join(from).exists() && !is_renamed(from))
+		.collect();
+
+	for shared in ["lua", "lua/autorun"] {
+		let Ok(entries) = fs::read_dir(dir.join(shared)) else {
+			continue;
+		};
+
+		for entry in entries.flatten() {
+			let relative = Path::new(shared).join(entry.file_name());
+			if names.contains(&entry.file_name()) && !is_renamed(&relative) {
+				found.push(relative);
+			}
+		}
+	}
+
+	found.sort();
+	found.dedup();
+	found
+}
+
+// Maps a path from the upstream layout to where the rules put it in the installed addon
+pub fn map_path(path: &str, rules: &[RenameRule]) -> String {
+	rules
+		.iter()
+		.find_map(|rule| {
+			if path == rule.from {
+				Some(rule.to.clone())
+			} else {
+				path.strip_prefix(rule.from.as_str())
+					.filter(|rest| rest.starts_with('/'))
+					.map(|rest| format!("{}{}", rule.to, rest))
+			}
+		})
+		.unwrap_or_else(|| path.to_string())
+}
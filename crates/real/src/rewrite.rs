@@ -0,0 +1,297 @@
+use serde::{Deserialize, Serialize};
+use std::{
+	fs,
+	path::{Path, PathBuf},
+};
+
+use crate::{config::RenameRule, print_log};
+
+// Functions whose first string argument is a path relative to the lua/ folder
+const PATH_FUNCTIONS: &[&str] = &["include", "AddCSLuaFile"];
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Rewrite {
+	pub file: PathBuf,
+	pub line: usize,
+	pub from: String,
+	pub to: String,
+}
+
+// Turns rename rules on lua/ paths into prefixes as seen by include(), longest first
+pub fn lua_prefixes(rules: &[RenameRule]) -> Vec<(String, String)> {
+	let mut prefixes: Vec<_> = rules
+		.iter()
+		.filter_map(|rule| {
+			let from = rule.from.strip_prefix("lua/")?;
+			let to = rule.to.strip_prefix("lua/")?;
+			(from != to).then(|| (from.to_string(), to.to_string()))
+		})
+		.collect();
+	prefixes.sort_by_key(|(from, _)| std::cmp::Reverse(from.len()));
+	prefixes
+}
+
+fn rewrite_path(path: &str, prefixes: &[(String, String)]) -> Option<String> {
+	prefixes.iter().find_map(|(from, to)| {
+		if path == from {
+			Some(to.clone())
+		} else {
+			path.strip_prefix(from.as_str())
+				.filter(|rest| rest.starts_with('/'))
+				.map(|rest| format!("{}{}", to, rest))
+		}
+	})
+}
+
+fn is_ident(c: u8) -> bool {
+	c.is_ascii_alphanumeric() || c == b'_'
+}
+
+// Skips whitespace and comments, returning the new position
+fn skip_trivia(src: &[u8], mut pos: usize) -> usize {
+	loop {
+		while pos < src.len() && src[pos].is_ascii_whitespace() {
+			pos += 1;
+		}
+		let rest = &src[pos..];
+		if rest.starts_with(b"--") || rest.starts_with(b"//") || rest.starts_with(b"/*") {
+			pos = skip_comment(src, pos);
+		} else {
+			return pos;
+		}
+	}
+}
+
+fn long_bracket_level(src: &[u8], pos: usize) -> Option<usize> {
+	let mut level = 0;
+	while src.get(pos + 1 + level) == Some(&b'=') {
+		level += 1;
+	}
+	(src.get(pos) == Some(&b'[') && src.get(pos + 1 + level) == Some(&b'[')).then_some(level)
+}
+
+fn skip_long_bracket(src: &[u8], pos: usize, level: usize) -> usize {
+	let closing = format!("]{}]", "=".repeat(level));
+	let start = pos + level + 2;
+	src[start.min(src.len())..]
+		.windows(closing.len())
+		.position(|window| window == closing.as_bytes())
+		.map_or(src.len(), |offset| start + offset + closing.len())
+}
+
+fn skip_comment(src: &[u8], pos: usize) -> usize {
+	let rest = &src[pos..];
+	if rest.starts_with(b"/*") {
+		return rest
+			.windows(2)
+			.skip(2)
+			.position(|window| window == b"*/")
+			.map_or(src.len(), |offset| pos + offset + 4);
+	}
+	if rest.starts_with(b"--")
+		&& let Some(level) = long_bracket_level(src, pos + 2)
+	{
+		return skip_long_bracket(src, pos + 2, level);
+	}
+	rest.iter().position(|&c| c == b'\n').map_or(src.len(), |offset| pos + offset)
+}
+
+// Returns the end of a quoted string starting at `pos` (just past the closing quote)
+fn skip_quoted(src: &[u8], pos: usize) -> usize {
+	let quote = src[pos];
+	let mut i = pos + 1;
+	while i < src.len() {
+		match src[i] {
+			b'\\' => i += 2,
+			b'\n' => return i,
+			c if c == quote => return i + 1,
+			_ => i += 1,
+		}
+	}
+	src.len()
+}
+
+// Rewrites string-literal paths passed to include()/AddCSLuaFile() in one source file
+fn rewrite_source(src: &[u8], prefixes: &[(String, String)]) -> (Vec<u8>, Vec<(usize, String, String)>) {
+	let mut out = Vec::with_capacity(src.len());
+	let mut changes = Vec::new();
+	let mut pos = 0;
+
+	while pos < src.len() {
+		let c = src[pos];
+		let rest = &src[pos..];
+
+		if rest.starts_with(b"--") || rest.starts_with(b"//") || rest.starts_with(b"/*") {
+			let end = skip_comment(src, pos);
+			out.extend_from_slice(&src[pos..end]);
+			pos = end;
+		} else if c == b'"' || c == b'\'' {
+			let end = skip_quoted(src, pos);
+			out.extend_from_slice(&src[pos..end]);
+			pos = end;
+		} else if let Some(level) = long_bracket_level(src, pos) {
+			let end = skip_long_bracket(src, pos, level);
+			out.extend_from_slice(&src[pos..end]);
+			pos = end;
+		} else if is_ident(c) {
+			let start = pos;
+			while pos < src.len() && is_ident(src[pos]) {
+				pos += 1;
+			}
+			out.extend_from_slice(&src[start..pos]);
+
+			// Only global calls, not fields or methods that happen to share the name
+			let word = &src[start..pos];
+			let is_member = start > 0 && matches!(src[start - 1], b'.' | b':');
+			if is_member || !PATH_FUNCTIONS.iter().any(|name| name.as_bytes() == word) {
+				continue;
+			}
+
+			let mut arg = skip_trivia(src, pos);
+			if src.get(arg) == Some(&b'(') {
+				arg = skip_trivia(src, arg + 1);
+			}
+			if !matches!(src.get(arg), Some(b'"' | b'\'')) {
+				continue;
+			}
+
+			let end = skip_quoted(src, arg);
+			let closed = end > arg + 1 && src[end - 1] == src[arg];
+			// A literal that is only part of a concatenated path can't be checked against the rules
+			let whole = !src[skip_trivia(src, end)..].starts_with(b"..");
+			let rewritten = (closed && whole)
+				.then(|| std::str::from_utf8(&src[arg + 1..end - 1]).ok())
+				.flatten()
+				.filter(|path| !path.contains('\\'))
+				.and_then(|path| rewrite_path(path, prefixes).map(|new| (path.to_string(), new)));
+
+			out.extend_from_slice(&src[pos..arg]);
+			match rewritten {
+				Some((old, new)) => {
+					let line = src[..arg].iter().filter(|&&c| c == b'\n').count() + 1;
+					out.push(src[arg]);
+					out.extend_from_slice(new.as_bytes());
+					out.extend_from_slice(&src[end - 1..end]);
+					changes.push((line, old, new));
+				}
+				None => out.extend_from_slice(&src[arg..end]),
+			}
+			pos = end;
+		} else {
+			out.push(c);
+			pos += 1;
+		}
+	}
+
+	(out, changes)
+}
+
+// Rewrites include paths in every .lua file below `root` and reports what changed
+pub fn rewrite_tree(root: &Path, prefixes: &[(String, String)]) -> Vec<Rewrite> {
+	let mut report = Vec::new();
+	if prefixes.is_empty() {
+		return report;
+	}
+
+	let mut pending = vec![root.to_path_buf()];
+	while let Some(dir) = pending.pop() {
+		let Ok(entries) = fs::read_dir(&dir) else {
+			continue;
+		};
+
+		for entry in entries.flatten() {
+			let path = entry.path();
+			if path.is_dir() {
+				pending.push(path);
+				continue;
+			}
+			if path.extension().and_then(|ext| ext.to_str()) != Some("lua") {
+				continue;
+			}
+
+			let Ok(src) = fs::read(&path) else {
+				continue;
+			};
+			let (out, changes) = rewrite_source(&src, prefixes);
+			if changes.is_empty() {
+				continue;
+			}
+
+			let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+			if let Err(e) = fs::write(&path, out) {
+				print_log(&format!("Failed to rewrite include paths in {}: {}", relative.display(), e));
+				continue;
+			}
+
+			report.extend(changes.into_iter().map(|(line, from, to)| Rewrite { file: relative.clone(), line, from, to }));
+		}
+	}
+
+	report.sort_by(|a, b| a.file.cmp(&b.file).then(a.line.cmp(&b.line)));
+	report
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn rewrite(src: &str) -> String {
+		let rules = [RenameRule { from: "lua/old".to_string(), to: "lua/new".to_string(), optional: false }];
+		let (out, _) = rewrite_source(src.as_bytes(), &lua_prefixes(&rules));
+		String::from_utf8(out).unwrap()
+	}
+
+	#[test]
+	fn rewrites_every_string_form() {
+		let cases = [
+			("include(\"old/init.lua\")", "include(\"new/init.lua\")"),
+			("include('old/init.lua')", "include('new/init.lua')"),
+			("include \"old/init.lua\"", "include \"new/init.lua\""),
+			("AddCSLuaFile( 'old/cl_init.lua' )", "AddCSLuaFile( 'new/cl_init.lua' )"),
+			("include(--[[ path ]] \"old/init.lua\")", "include(--[[ path ]] \"new/init.lua\")"),
+			("include(\"old\")", "include(\"new\")"),
+		];
+		for (src, expected) in cases {
+			assert_eq!(rewrite(src), expected);
+		}
+	}
+
+	#[test]
+	fn leaves_dynamic_paths_alone() {
+		let cases = [
+			"include(\"old/\" .. name)",
+			"include('old/' ..name)",
+			"include(dir .. \"old/init.lua\")",
+			"include(path)",
+			"include([[old/init.lua]])",
+			"include(\"older/init.lua\")",
+			"self.include(\"old/init.lua\")",
+			"obj:AddCSLuaFile(\"old/init.lua\")",
+		];
+		for src in cases {
+			assert_eq!(rewrite(src), src);
+		}
+	}
+
+	#[test]
+	fn ignores_comments_and_strings() {
+		let cases = [
+			"-- include(\"old/init.lua\")",
+			"// include(\"old/init.lua\")",
+			"/* include(\"old/init.lua\") */",
+			"--[==[ include(\"old/init.lua\") ]==]",
+			"print(\"include('old/init.lua')\")",
+			"local s = [[include(\"old/init.lua\")]]",
+		];
+		for src in cases {
+			assert_eq!(rewrite(src), src);
+		}
+	}
+
+	#[test]
+	fn reports_changed_lines() {
+		let rules = [RenameRule { from: "lua/old".to_string(), to: "lua/new".to_string(), optional: false }];
+		let (_, changes) = rewrite_source(b"local a = 1\n\ninclude('old/b.lua')", &lua_prefixes(&rules));
+		assert_eq!(changes, vec![(3, "old/b.lua".to_string(), "new/b.lua".to_string())]);
+	}
+}
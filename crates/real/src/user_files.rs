@@ -0,0 +1,134 @@
+use std::{fs, path::Path};
+
+use crate::{
+	filter::glob_match,
+	manifest::{self, InstallManifest},
+	print_log,
+};
+
+// Last upstream version of every user-owned file, the base for three-way comparisons
+const PRISTINE_DIR: &str = "garrysmod/data/gm_integration/pristine";
+
+// Files larger than this only get a byte count in the diff summary
+const DIFF_LINE_LIMIT: usize = 5000;
+
+pub struct Preserved {
+	// User-owned files whose local version was kept
+	pub kept: Vec<String>,
+	// Upstream content of every user-owned file, saved as the new pristine copies
+	upstream: Vec<(String, Vec<u8>)>,
+}
+
+pub fn is_user_owned(relative: &str, patterns: &[String]) -> bool {
+	patterns.iter().any(|pattern| glob_match(pattern, relative))
+}
+
+// Number of lines added and removed between `from` and `to`
+fn line_changes(from: &str, to: &str) -> Option<(usize, usize)> {
+	let from: Vec<&str> = from.lines().collect();
+	let to: Vec<&str> = to.lines().collect();
+	if from.len() > DIFF_LINE_LIMIT || to.len() > DIFF_LINE_LIMIT {
+		return None;
+	}
+
+	// Longest common subsequence, one row at a time
+	let mut previous = vec![0usize; to.len() + 1];
+	for line in &from {
+		let mut current = vec![0usize; to.len() + 1];
+		for (j, other) in to.iter().enumerate() {
+			current[j + 1] = if line == other { previous[j] + 1 } else { current[j].max(previous[j + 1]) };
+		}
+		previous = current;
+	}
+
+	let common = previous[to.len()];
+	Some((to.len() - common, from.len() - common))
+}
+
+fn summarize(from: &[u8], to: &[u8]) -> String {
+	match line_changes(&String::from_utf8_lossy(from), &String::from_utf8_lossy(to)) {
+		Some((added, removed)) => format!("+{} -{} lines", added, removed),
+		None => format!("{} -> {} bytes", from.len(), to.len()),
+	}
+}
+
+// Carries local edits of user-owned files over into the staged release. A local file is kept
+// when it differs from the pristine copy of the previous release, and a conflict is reported
+// when upstream changed the same file
+pub fn preserve(staging: &Path, addon_dir: &Path, patterns: &[String], installed: Option<&InstallManifest>) -> Preserved {
+	let mut preserved = Preserved { kept: Vec::new(), upstream: Vec::new() };
+	if patterns.is_empty() {
+		return preserved;
+	}
+
+	for relative in manifest::list_files(staging) {
+		if !is_user_owned(&relative, patterns) {
+			continue;
+		}
+
+		let Ok(upstream) = fs::read(staging.join(&relative)) else {
+			continue;
+		};
+		let local = fs::read(addon_dir.join(&relative)).ok();
+		let pristine = fs::read(Path::new(PRISTINE_DIR).join(&relative)).ok();
+		// Installs from before pristine copies were kept only have the manifest hash
+		let base_hash = pristine
+			.as_deref()
+			.map(manifest::hash_bytes)
+			.or_else(|| installed.and_then(|m| m.files.get(&relative)).map(|record| record.sha256.clone()));
+
+		let keep_local = match (&local, &base_hash) {
+			(None, _) => false,
+			(Some(local), _) if local == &upstream => false,
+			// Without a base there is no telling an edit from an older upstream version, so the
+			// local file stays and upstream becomes the base for later updates
+			(Some(_), None) => true,
+			(Some(local), Some(base_hash)) => *base_hash != manifest::hash_bytes(local),
+		};
+
+		if let Some(local) = local.filter(|_| keep_local) {
+			let upstream_changed = base_hash.as_deref() != Some(manifest::hash_bytes(&upstream).as_str());
+			if !upstream_changed {
+				print_log(&format!("Keeping local version of {}", relative));
+			} else if let Some(pristine) = &pristine {
+				print_log(&format!(
+					"Warning: {} was changed both locally ({}) and upstream ({}), keeping the local version. The new upstream version is in {}",
+					relative,
+					summarize(pristine, &local),
+					summarize(pristine, &upstream),
+					Path::new(PRISTINE_DIR).join(&relative).display()
+				));
+			} else {
+				print_log(&format!(
+					"Warning: {} differs from the new upstream version ({}), keeping the local version. Merge the new upstream version from {} by hand",
+					relative,
+					summarize(&upstream, &local),
+					Path::new(PRISTINE_DIR).join(&relative).display()
+				));
+			}
+
+			if let Err(e) = fs::write(staging.join(&relative), &local) {
+				print_log(&format!("Failed to keep local version of {}: {}", relative, e));
+				continue;
+			}
+			preserved.kept.push(relative.clone());
+		}
+
+		preserved.upstream.push((relative, upstream));
+	}
+
+	preserved
+}
+
+// Stores the upstream versions of user-owned files once the update is installed
+pub fn save_pristine(preserved: &Preserved) {
+	for (relative, content) in &preserved.upstream {
+		let path = Path::new(PRISTINE_DIR).join(relative);
+		if let Some(parent) = path.parent() {
+			let _ = fs::create_dir_all(parent);
+		}
+		if let Err(e) = fs::write(&path, content) {
+			print_log(&format!("Failed to save pristine copy of {}: {}", relative, e));
+		}
+	}
+}